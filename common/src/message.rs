@@ -1,7 +1,4 @@
-use std::{
-    io::{Error, ErrorKind},
-    marker::PhantomData,
-};
+use std::{io::Error, marker::PhantomData};
 
 use serde::{Deserialize, Serialize};
 use tokio_util::{
@@ -9,7 +6,10 @@ use tokio_util::{
     codec::{Decoder, Encoder},
 };
 
-use crate::sprites::Sprite;
+use crate::{
+    buff_effect::ExceptionEffect,
+    sprites::{Sprite, VisibleSprite, skills::BattleStat},
+};
 
 /// 客户端发往服务端的消息结构
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,16 +24,14 @@ pub struct ClientMessage {
 pub enum ClientPayload {
     /// 心跳检测，
     Ping,
-    Register,
-    Login {
-        username: String,
-        password: String,
-    },
+    /// 注册账号
+    Register { username: String, password: String },
+    /// 使用用户名密码登录
+    Login { username: String, password: String },
+    /// 使用 refresh token 换取新的 token
+    RefreshToken { refresh_token: String },
     /// 认证成功，后续请求需要携带 token
-    Authenticated {
-        token: String,
-        action: ClientAction,
-    },
+    Authenticated { token: String, action: ClientAction },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,25 +46,144 @@ pub enum ClientAction {
     },
     /// 客户端请求加载出战的6只精灵数据
     SpriteTeam,
+    /// 开始匹配指定模式的对战
+    StartMatchmaking {
+        mode: MatchMode,
+    },
     /// 对战中, 玩家提交操作
     RoomAction(RoomAction),
 }
 
+/// 对战模式, 不同模式使用不同的对战规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MatchMode {
+    /// 排位赛
+    Ranked,
+    /// 休闲赛
+    Casual,
+    /// 友谊赛
+    Friendly,
+}
+
+impl MatchMode {
+    pub const ALL: [MatchMode; 3] = [MatchMode::Ranked, MatchMode::Casual, MatchMode::Friendly];
+}
+
+/// 对战中玩家提交的操作
+///
+/// 不携带玩家ID，服务端使用会话中已认证的玩家ID标记操作的发起者
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum RoomAction {
     /// 玩家提交技能
-    SkillAttack { player_id: u64, skill_id: u64 },
+    SkillAttack { skill_id: u64 },
     /// 玩家切换精灵
-    SwitchSprite { player_id: u64, sprite_index: usize },
+    SwitchSprite { sprite_index: usize },
     /// 玩家使用道具(加HP/加PP)
-    UseItem { player_id: u64, item_id: u64 },
+    UseItem { item_id: u64 },
     /// 玩家使用捕捉
     /// 1. 检查是否有精灵可以捕捉
     /// 2. 检查是否有空间可以存放捕捉到的精灵
     /// 3. 捕捉精灵
     CatchSprite,
     /// 逃跑
-    Escape(u64),
+    Escape,
+}
+
+/// 房间操作被拒绝的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoomActionError {
+    /// 玩家不在房间中
+    NotInRoom,
+    /// 房间已关闭
+    RoomClosed,
+    /// 上场的精灵没有学会该技能
+    UnknownSkill,
+    /// 技能的PP已耗尽
+    NoPP,
+    /// 上场的精灵本回合无法行动
+    CannotAct,
+    /// 目标精灵不存在或已经倒下
+    InvalidSprite,
+    /// 当前阶段不能提交该操作
+    WrongPhase,
+    /// 当前对战模式不允许使用道具
+    ItemsNotAllowed,
+    /// 当前对战不能捕捉精灵
+    CatchNotAllowed,
+}
+
+/// 回合结算中发生的事件, 按发生的先后顺序排列
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BattleEvent {
+    /// 玩家的精灵使用了技能
+    SkillUsed { player_id: u64, skill_id: u64 },
+    /// 玩家的精灵受到伤害
+    Damage {
+        player_id: u64,
+        sprite_index: usize,
+        damage: u16,
+        /// 属性克制倍数
+        multiplier: f32,
+        is_critical: bool,
+        remaining_hp: u16,
+    },
+    /// 技能未命中
+    Miss { player_id: u64, skill_id: u64 },
+    /// 玩家的精灵陷入异常状态
+    StatusApplied {
+        player_id: u64,
+        sprite_index: usize,
+        effect: ExceptionEffect,
+    },
+    /// 玩家的精灵受到异常状态的持续伤害
+    StatusDamage {
+        player_id: u64,
+        sprite_index: usize,
+        effect: ExceptionEffect,
+        damage: u16,
+        remaining_hp: u16,
+    },
+    /// 玩家的精灵能力等级发生变化, `stages` 为实际变化的等级数
+    StatChanged {
+        player_id: u64,
+        sprite_index: usize,
+        stat: BattleStat,
+        stages: i8,
+        /// 变化后的能力等级
+        current: i8,
+    },
+    /// 玩家的精灵受到控制, 本回合无法行动
+    CannotAct {
+        player_id: u64,
+        effect: ExceptionEffect,
+    },
+    /// 玩家的精灵解除了异常状态
+    StatusCleared {
+        player_id: u64,
+        sprite_index: usize,
+        effect: ExceptionEffect,
+    },
+    /// 玩家的精灵倒下
+    Fainted { player_id: u64, sprite_index: usize },
+    /// 玩家切换了上场的精灵
+    Switched { player_id: u64, sprite_index: usize },
+}
+
+/// 战斗结束的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BattleEndReason {
+    /// 一方的精灵全部倒下
+    AllFainted,
+    /// 一方逃跑
+    Escape,
+    /// 等待超时
+    Timeout,
+    /// 一方超时未提交而判负
+    Forfeit,
+    /// 达到回合数上限
+    TurnLimit,
+    /// 房间被关闭
+    Closed,
 }
 
 /// 服务端发往客户端的消息结构
@@ -85,14 +202,61 @@ pub enum ServerPayload {
     Chat {
         content: String,
     },
+    /// 注册成功，携带新账号的玩家ID
+    RegisterSuccess(u64),
+    /// 注册失败，携带失败原因
+    RegisterFailed(String),
     /// 登录成功
-    LoginSuccess(String),
+    LoginSuccess {
+        token: String,
+        refresh_token: String,
+    },
+    /// 下发新的 token，客户端请求刷新或 token 即将过期时由服务端主动推送
+    TokenRefreshed {
+        token: String,
+        refresh_token: String,
+    },
     /// 登录失败
     LoginFailed,
     /// 认证失败
     AuthFailed,
     /// 精灵队伍
     SpriteTeam(Vec<Sprite>),
+    /// 房间操作被拒绝
+    RoomActionRejected {
+        action: RoomAction,
+        reason: RoomActionError,
+    },
+    /// 对战开始, 携带对手可见的精灵队伍
+    BattleStart {
+        room_id: u64,
+        opponent_id: u64,
+        opponent_team: Vec<VisibleSprite>,
+    },
+    /// 回合开始, `deadline` 为提交操作的截止时间(Unix 毫秒时间戳)
+    TurnStart {
+        turn: u32,
+        deadline: i64,
+    },
+    /// 回合结算结果
+    TurnResult {
+        turn: u32,
+        events: Vec<BattleEvent>,
+    },
+    /// 上场的精灵倒下, 需要在截止时间前选择替换的精灵
+    ForceSwitch {
+        deadline: i64,
+    },
+    /// 玩家在回合结算之外替换了上场的精灵, 如替换倒下的精灵或不占用回合的切换
+    SpriteReplaced {
+        player_id: u64,
+        sprite_index: usize,
+    },
+    /// 对战结束, 平局时 `winner` 为空
+    BattleEnd {
+        winner: Option<u64>,
+        reason: BattleEndReason,
+    },
 }
 
 /// 游戏消息编码器/解码器
@@ -129,7 +293,7 @@ where
     fn encode(&mut self, item: S, dst: &mut BytesMut) -> Result<(), Self::Error> {
         // 序列化消息
         let bytes = bincode::serde::encode_to_vec(item, bincode::config::standard())
-            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+            .map_err(Error::other)?;
 
         // 写入消息内容长度前缀
         dst.put_u32(bytes.len() as u32);
@@ -172,7 +336,7 @@ where
         let msg_bytes = src.split_to(len);
         // 反序列化消息
         let (msg, _) = bincode::serde::decode_from_slice(&msg_bytes, bincode::config::standard())
-            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+            .map_err(Error::other)?;
         Ok(Some(msg))
     }
 }
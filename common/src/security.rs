@@ -1,7 +1,36 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
 use chrono::{Duration, Utc};
-use jsonwebtoken::{DecodingKey, EncodingKey, Header};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+    errors::{Error, ErrorKind},
+};
 use serde::{Deserialize, Serialize};
 
+/// 签名密钥的最小长度(字节)
+const MIN_SECRET_LEN: usize = 32;
+/// 默认的 refresh token 有效时间(秒)
+const DEFAULT_REFRESH_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+/// 默认在 token 过期前多久主动续期(秒)
+const DEFAULT_REFRESH_BEFORE_SECS: i64 = 5 * 60;
+
+/// 本进程内签发 token 的计数, 与签发时间一起组成 token ID
+static TOKEN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// token 类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenType {
+    /// 访问 token, 请求业务接口时携带
+    Access,
+    /// 刷新 token, 只能用于换取新的访问 token
+    Refresh,
+}
+
 /// JWT 声明
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -9,14 +38,275 @@ pub struct Claims {
     sub: u64,
     /// 过期时间
     exp: usize,
+    /// 签发时间
+    iat: usize,
+    /// 签发者
+    iss: String,
+    /// 接收者
+    aud: String,
+    /// token 类型
+    typ: TokenType,
+    /// token ID, 每次签发都不同, 用于识别被轮换掉的 refresh token
+    #[serde(default)]
+    jti: String,
+}
+
+impl Claims {
+    /// token 所属的玩家ID
+    pub fn subject(&self) -> u64 {
+        self.sub
+    }
+
+    /// 过期时间(Unix 时间戳, 秒)
+    pub fn expires_at(&self) -> usize {
+        self.exp
+    }
+
+    /// token ID
+    pub fn id(&self) -> &str {
+        &self.jti
+    }
 }
 
-const JWT_SECRET: &[u8] = b"my_secret_key";
+/// token 配置错误
+#[derive(Debug)]
+pub struct TokenConfigError(String);
+
+impl fmt::Display for TokenConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "token 配置错误: {}", self.0)
+    }
+}
+
+impl std::error::Error for TokenConfigError {}
+
+/// 配置文件中的签名密钥
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenKeyConfig {
+    /// 密钥ID, 签发 token 时写入 header 的 `kid`
+    pub kid: String,
+    /// 密钥内容
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// 密钥文件路径, 与 `secret` 二选一, 相对路径相对于配置文件所在目录
+    #[serde(default)]
+    pub secret_file: Option<PathBuf>,
+}
+
+/// token 配置文件
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenConfigFile {
+    /// 签名算法, 只支持 HS256/HS384/HS512
+    pub algorithm: Algorithm,
+    pub issuer: String,
+    pub audience: String,
+    /// token 有效时间(秒)
+    pub ttl_secs: i64,
+    /// refresh token 有效时间(秒)
+    #[serde(default = "default_refresh_ttl_secs")]
+    pub refresh_ttl_secs: i64,
+    /// 在 token 过期前多久主动下发新的 token(秒)
+    #[serde(default = "default_refresh_before_secs")]
+    pub refresh_before_secs: i64,
+    /// 用于签发新 token 的密钥ID
+    pub active_kid: String,
+    /// 所有可用于校验的密钥, 轮换密钥时保留旧密钥, 旧 token 在过期前仍然有效
+    pub keys: Vec<TokenKeyConfig>,
+}
+
+fn default_refresh_ttl_secs() -> i64 {
+    DEFAULT_REFRESH_TTL_SECS
+}
+
+fn default_refresh_before_secs() -> i64 {
+    DEFAULT_REFRESH_BEFORE_SECS
+}
+
+/// token 签发与校验配置
+///
+/// 签发时使用 `active_kid` 对应的密钥, 并把 `kid` 写入 header;
+/// 校验时根据 header 中的 `kid` 选择密钥, 因此密钥轮换期间旧 token 仍然有效
+#[derive(Clone)]
+pub struct TokenConfig {
+    algorithm: Algorithm,
+    issuer: String,
+    audience: String,
+    ttl: Duration,
+    refresh_ttl: Duration,
+    refresh_before: Duration,
+    active_kid: String,
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl fmt::Debug for TokenConfig {
+    // 不输出密钥内容
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenConfig")
+            .field("algorithm", &self.algorithm)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("ttl", &self.ttl)
+            .field("refresh_ttl", &self.refresh_ttl)
+            .field("refresh_before", &self.refresh_before)
+            .field("active_kid", &self.active_kid)
+            .field("kids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl TokenConfig {
+    /// 创建 token 配置
+    ///
+    /// # 参数
+    ///
+    /// * `algorithm` - 签名算法, 只支持 HS256/HS384/HS512
+    /// * `issuer` - 签发者
+    /// * `audience` - 接收者
+    /// * `ttl` - token 有效时间
+    /// * `active_kid` - 用于签发新 token 的密钥ID, 必须存在于 `keys` 中
+    /// * `keys` - 所有可用于校验的密钥 `(kid, secret)`
+    pub fn new(
+        algorithm: Algorithm,
+        issuer: impl Into<String>,
+        audience: impl Into<String>,
+        ttl: Duration,
+        active_kid: impl Into<String>,
+        keys: impl IntoIterator<Item = (String, Vec<u8>)>,
+    ) -> Result<Self, TokenConfigError> {
+        if !matches!(
+            algorithm,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(TokenConfigError(format!(
+                "不支持的签名算法 {:?}, 只支持 HS256/HS384/HS512",
+                algorithm
+            )));
+        }
+        if ttl <= Duration::zero() {
+            return Err(TokenConfigError("token 有效时间必须大于 0".to_owned()));
+        }
+        let mut key_map = HashMap::new();
+        for (kid, secret) in keys {
+            if secret.len() < MIN_SECRET_LEN {
+                return Err(TokenConfigError(format!(
+                    "密钥 {} 的长度不能少于 {} 字节",
+                    kid, MIN_SECRET_LEN
+                )));
+            }
+            if key_map.insert(kid.clone(), secret).is_some() {
+                return Err(TokenConfigError(format!("密钥 {} 重复", kid)));
+            }
+        }
+        let active_kid = active_kid.into();
+        if !key_map.contains_key(&active_kid) {
+            return Err(TokenConfigError(format!(
+                "active_kid {} 不在密钥列表中",
+                active_kid
+            )));
+        }
+        Ok(Self {
+            algorithm,
+            issuer: issuer.into(),
+            audience: audience.into(),
+            ttl,
+            refresh_ttl: Duration::seconds(DEFAULT_REFRESH_TTL_SECS),
+            refresh_before: Duration::seconds(DEFAULT_REFRESH_BEFORE_SECS),
+            active_kid,
+            keys: key_map,
+        })
+    }
+
+    /// 设置 refresh token 的有效时间, 以及在访问 token 过期前多久主动续期
+    pub fn with_refresh(
+        mut self,
+        refresh_ttl: Duration,
+        refresh_before: Duration,
+    ) -> Result<Self, TokenConfigError> {
+        if refresh_ttl <= self.ttl {
+            return Err(TokenConfigError(
+                "refresh token 有效时间必须大于 token 有效时间".to_owned(),
+            ));
+        }
+        if refresh_before < Duration::zero() || refresh_before >= self.ttl {
+            return Err(TokenConfigError(
+                "主动续期时间必须在 0 到 token 有效时间之间".to_owned(),
+            ));
+        }
+        self.refresh_ttl = refresh_ttl;
+        self.refresh_before = refresh_before;
+        Ok(self)
+    }
+
+    /// 从 JSON 配置文件加载 token 配置
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, TokenConfigError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| TokenConfigError(format!("读取 {} 失败: {}", path.display(), e)))?;
+        let config: TokenConfigFile = serde_json::from_str(&content)
+            .map_err(|e| TokenConfigError(format!("解析 {} 失败: {}", path.display(), e)))?;
+        Self::from_config_file(config, path.parent().unwrap_or(Path::new(".")))
+    }
+
+    /// 从配置创建 token 配置, 密钥文件的相对路径相对于 `base_dir`
+    pub fn from_config_file(
+        config: TokenConfigFile,
+        base_dir: &Path,
+    ) -> Result<Self, TokenConfigError> {
+        let mut keys = Vec::with_capacity(config.keys.len());
+        for key in config.keys {
+            let secret = match (key.secret, key.secret_file) {
+                (Some(secret), None) => secret.into_bytes(),
+                (None, Some(secret_file)) => {
+                    let secret_file = base_dir.join(secret_file);
+                    let secret = fs::read(&secret_file).map_err(|e| {
+                        TokenConfigError(format!(
+                            "读取密钥文件 {} 失败: {}",
+                            secret_file.display(),
+                            e
+                        ))
+                    })?;
+                    // 去掉密钥文件末尾的换行
+                    secret.trim_ascii_end().to_vec()
+                }
+                _ => {
+                    return Err(TokenConfigError(format!(
+                        "密钥 {} 需要且只能配置 secret 或 secret_file 其中之一",
+                        key.kid
+                    )));
+                }
+            };
+            keys.push((key.kid, secret));
+        }
+        Self::new(
+            config.algorithm,
+            config.issuer,
+            config.audience,
+            Duration::seconds(config.ttl_secs),
+            config.active_kid,
+            keys,
+        )?
+        .with_refresh(
+            Duration::seconds(config.refresh_ttl_secs),
+            Duration::seconds(config.refresh_before_secs),
+        )
+    }
+
+    /// token 有效时间
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// 在 token 过期前多久主动续期
+    pub fn refresh_before(&self) -> Duration {
+        self.refresh_before
+    }
+}
 
 /// 生成JWT token
 ///
 /// # 参数
 ///
+/// * `config` - token 配置
 /// * `user_id` - 玩家ID
 ///
 /// # 返回值
@@ -26,32 +316,82 @@ const JWT_SECRET: &[u8] = b"my_secret_key";
 /// # 示例
 ///
 /// ```
-/// use common::security::genenrate_token;
+/// use chrono::Duration;
+/// use common::security::{TokenConfig, genenrate_token};
+/// use jsonwebtoken::Algorithm;
 ///
-/// let token = genenrate_token(123);
+/// let config = TokenConfig::new(
+///     Algorithm::HS256,
+///     "game_server",
+///     "game_client",
+///     Duration::hours(1),
+///     "key-1",
+///     [("key-1".to_owned(), vec![7; 32])],
+/// )
+/// .unwrap();
+/// let token = genenrate_token(&config, 123);
 /// ```
-pub fn genenrate_token(user_id: u64) -> String {
-    let expiration = Utc::now()
-        .checked_add_signed(Duration::hours(1))
-        .expect("有效时间戳")
-        .timestamp() as usize;
+pub fn genenrate_token(config: &TokenConfig, user_id: u64) -> String {
+    issue_token(config, user_id, TokenType::Access, config.ttl).0
+}
+
+/// 生成 refresh token, 只能用于换取新的访问 token
+///
+/// # 参数
+///
+/// * `config` - token 配置
+/// * `user_id` - 玩家ID
+pub fn generate_refresh_token(config: &TokenConfig, user_id: u64) -> String {
+    issue_refresh_token(config, user_id).0
+}
+
+/// 生成 refresh token, 同时返回它的声明
+///
+/// 服务器需要记录声明中的 token ID, 以便在轮换后拒绝旧的 refresh token
+pub fn issue_refresh_token(config: &TokenConfig, user_id: u64) -> (String, Claims) {
+    issue_token(config, user_id, TokenType::Refresh, config.refresh_ttl)
+}
+
+fn issue_token(
+    config: &TokenConfig,
+    user_id: u64,
+    typ: TokenType,
+    ttl: Duration,
+) -> (String, Claims) {
+    let now = Utc::now();
+    let expiration = now.checked_add_signed(ttl).expect("有效时间戳").timestamp() as usize;
 
     let claims = Claims {
         sub: user_id,
         exp: expiration,
+        iat: now.timestamp() as usize,
+        iss: config.issuer.clone(),
+        aud: config.audience.clone(),
+        typ,
+        jti: format!(
+            "{:x}-{:x}",
+            now.timestamp_nanos_opt().unwrap_or_default(),
+            TOKEN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ),
     };
-    jsonwebtoken::encode(
-        &Header::default(),
+    let mut header = Header::new(config.algorithm);
+    header.kid = Some(config.active_kid.clone());
+    let token = jsonwebtoken::encode(
+        &header,
         &claims,
-        &EncodingKey::from_secret(JWT_SECRET),
+        &EncodingKey::from_secret(&config.keys[&config.active_kid]),
     )
-    .expect("生成token失败")
+    .expect("生成token失败");
+    (token, claims)
 }
 
 /// 验证JWT token
 ///
+/// 根据 header 中的 `kid` 选择密钥, 并校验签名算法、过期时间、签发者与接收者
+///
 /// # 参数
 ///
+/// * `config` - token 配置
 /// * `token` - 待验证的JWT token字符串
 ///
 /// # 返回值
@@ -61,17 +401,160 @@ pub fn genenrate_token(user_id: u64) -> String {
 /// # 示例
 ///
 /// ```
-/// use common::security::genenrate_token;
-/// use common::security::validate_token;
+/// use chrono::Duration;
+/// use common::security::{TokenConfig, genenrate_token, validate_token};
+/// use jsonwebtoken::Algorithm;
 ///
-/// let token = genenrate_token(123);
-/// let claims = validate_token(&token).unwrap();
+/// let config = TokenConfig::new(
+///     Algorithm::HS256,
+///     "game_server",
+///     "game_client",
+///     Duration::hours(1),
+///     "key-1",
+///     [("key-1".to_owned(), vec![7; 32])],
+/// )
+/// .unwrap();
+/// let token = genenrate_token(&config, 123);
+/// let claims = validate_token(&config, &token).unwrap();
 /// ```
-pub fn validate_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    jsonwebtoken::decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(JWT_SECRET),
-        &jsonwebtoken::Validation::default(),
-    )
-    .map(|data| data.claims)
+pub fn validate_token(config: &TokenConfig, token: &str) -> Result<Claims, Error> {
+    decode_token(config, token, TokenType::Access)
+}
+
+/// 验证 refresh token, 访问 token 不能当作 refresh token 使用
+///
+/// # 参数
+///
+/// * `config` - token 配置
+/// * `token` - 待验证的 refresh token 字符串
+pub fn validate_refresh_token(config: &TokenConfig, token: &str) -> Result<Claims, Error> {
+    decode_token(config, token, TokenType::Refresh)
+}
+
+fn decode_token(config: &TokenConfig, token: &str, typ: TokenType) -> Result<Claims, Error> {
+    let header = jsonwebtoken::decode_header(token)?;
+    let secret = header
+        .kid
+        .as_ref()
+        .and_then(|kid| config.keys.get(kid))
+        .ok_or_else(|| Error::from(ErrorKind::InvalidToken))?;
+
+    let mut validation = Validation::new(config.algorithm);
+    validation.set_issuer(&[&config.issuer]);
+    validation.set_audience(&[&config.audience]);
+    let claims =
+        jsonwebtoken::decode::<Claims>(token, &DecodingKey::from_secret(secret), &validation)?
+            .claims;
+    if claims.typ != typ {
+        return Err(ErrorKind::InvalidToken.into());
+    }
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(active_kid: &str, kids: &[&str]) -> TokenConfig {
+        TokenConfig::new(
+            Algorithm::HS256,
+            "game_server",
+            "game_client",
+            Duration::hours(1),
+            active_kid,
+            kids.iter()
+                .map(|kid| (kid.to_string(), kid.repeat(32).into_bytes())),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old_config = config("old", &["old"]);
+        let old_token = genenrate_token(&old_config, 1);
+
+        // 轮换密钥: 新 token 使用新密钥签发, 旧 token 仍然有效
+        let rotated_config = config("new", &["old", "new"]);
+        let new_token = genenrate_token(&rotated_config, 2);
+        assert_eq!(validate_token(&rotated_config, &old_token).unwrap().sub, 1);
+        assert_eq!(validate_token(&rotated_config, &new_token).unwrap().sub, 2);
+
+        // 移除旧密钥后旧 token 失效
+        let retired_config = config("new", &["new"]);
+        assert!(validate_token(&retired_config, &old_token).is_err());
+        assert!(validate_token(&old_config, &new_token).is_err());
+    }
+
+    #[test]
+    fn test_refresh_token_type() {
+        let config = config("key", &["key"]);
+        let token = genenrate_token(&config, 1);
+        let refresh_token = generate_refresh_token(&config, 1);
+
+        let claims = validate_refresh_token(&config, &refresh_token).unwrap();
+        assert_eq!(claims.sub, 1);
+        assert!(claims.expires_at() > validate_token(&config, &token).unwrap().expires_at());
+        // 两种 token 不能混用
+        assert!(validate_token(&config, &refresh_token).is_err());
+        assert!(validate_refresh_token(&config, &token).is_err());
+    }
+
+    #[test]
+    fn test_refresh_token_id() {
+        let config = config("key", &["key"]);
+        let (token, claims) = issue_refresh_token(&config, 1);
+        let other = generate_refresh_token(&config, 1);
+
+        assert_eq!(
+            validate_refresh_token(&config, &token).unwrap().id(),
+            claims.id()
+        );
+        // 同一时刻签发的 token ID 也不相同
+        assert_ne!(
+            validate_refresh_token(&config, &other).unwrap().id(),
+            claims.id()
+        );
+    }
+
+    #[test]
+    fn test_reject_other_deployment() {
+        let config_a = config("key", &["key"]);
+        let config_b = TokenConfig::new(
+            Algorithm::HS256,
+            "other_server",
+            "game_client",
+            Duration::hours(1),
+            "key",
+            [("key".to_owned(), "key".repeat(32).into_bytes())],
+        )
+        .unwrap();
+        let token = genenrate_token(&config_b, 1);
+        assert!(validate_token(&config_a, &token).is_err());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(
+            TokenConfig::new(
+                Algorithm::HS256,
+                "game_server",
+                "game_client",
+                Duration::hours(1),
+                "key",
+                [("key".to_owned(), b"short".to_vec())],
+            )
+            .is_err()
+        );
+        assert!(
+            TokenConfig::new(
+                Algorithm::RS256,
+                "game_server",
+                "game_client",
+                Duration::hours(1),
+                "key",
+                [("key".to_owned(), vec![0; 32])],
+            )
+            .is_err()
+        );
+    }
 }
@@ -3,12 +3,14 @@ pub mod skills;
 
 use serde::{Deserialize, Serialize};
 
-use crate::sprites::skills::Skill;
+use crate::sprites::{attributes::Attribute, skills::Skill};
 
 /// 玩家配置的精灵数据
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sprite {
     pub id: u64,
+    /// 精灵属性, 一到两个, 两个单属性可以组成双属性
+    pub attributes: Vec<Attribute>,
     /// 精灵等级
     pub level: u8,
     /// 精灵当前经验值
@@ -36,3 +38,23 @@ pub struct Sprite {
     /// 携带的技能
     pub skills: Vec<Skill>,
 }
+
+/// 对手可见的精灵信息, 不包含面板数据与技能
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VisibleSprite {
+    pub id: u64,
+    pub level: u8,
+    pub hp: u16,
+    pub max_hp: u16,
+}
+
+impl From<&Sprite> for VisibleSprite {
+    fn from(sprite: &Sprite) -> Self {
+        Self {
+            id: sprite.id,
+            level: sprite.level,
+            hp: sprite.hp,
+            max_hp: sprite.max_hp,
+        }
+    }
+}
@@ -62,3 +62,88 @@ pub enum Attribute {
     #[default]
     None,
 }
+
+impl Attribute {
+    /// 所有的属性
+    pub const ALL: [Attribute; 24] = [
+        Attribute::Jin,
+        Attribute::Mu,
+        Attribute::Shui,
+        Attribute::Huo,
+        Attribute::Tu,
+        Attribute::Yi,
+        Attribute::Guai,
+        Attribute::Mo,
+        Attribute::Yao,
+        Attribute::Feng,
+        Attribute::Du,
+        Attribute::Lei,
+        Attribute::Huan,
+        Attribute::Bing,
+        Attribute::Ling,
+        Attribute::JiXie,
+        Attribute::Huofeng,
+        Attribute::Wuling,
+        Attribute::Seng,
+        Attribute::Tonghuan,
+        Attribute::ShuiYao,
+        Attribute::Yin,
+        Attribute::Special,
+        Attribute::None,
+    ];
+
+    /// 双属性及组成它的两个单属性
+    ///
+    /// 火风(`Huofeng`)由火与风组成, 而 `Feng` 是凤属性, 没有对应的风属性, 所以火风不能由两个单属性组成
+    pub const DUAL: [(Attribute, Attribute, Attribute); 3] = [
+        (Attribute::Mu, Attribute::Ling, Attribute::Wuling),
+        (Attribute::Tu, Attribute::Huan, Attribute::Tonghuan),
+        (Attribute::Shui, Attribute::Yao, Attribute::ShuiYao),
+    ];
+
+    /// 查找两个单属性组成的双属性, 与顺序无关
+    pub fn dual(first: Attribute, second: Attribute) -> Option<Attribute> {
+        Self::DUAL.iter().find_map(|(a, b, dual)| {
+            ((*a, *b) == (first, second) || (*b, *a) == (first, second)).then_some(*dual)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_all_covers_every_variant() {
+        // 新增属性时这里的 match 无法通过编译, 提醒同时更新 `Attribute::ALL`
+        let index = |attribute: Attribute| match attribute {
+            Attribute::Jin => 0,
+            Attribute::Mu => 1,
+            Attribute::Shui => 2,
+            Attribute::Huo => 3,
+            Attribute::Tu => 4,
+            Attribute::Yi => 5,
+            Attribute::Guai => 6,
+            Attribute::Mo => 7,
+            Attribute::Yao => 8,
+            Attribute::Feng => 9,
+            Attribute::Du => 10,
+            Attribute::Lei => 11,
+            Attribute::Huan => 12,
+            Attribute::Bing => 13,
+            Attribute::Ling => 14,
+            Attribute::JiXie => 15,
+            Attribute::Huofeng => 16,
+            Attribute::Wuling => 17,
+            Attribute::Seng => 18,
+            Attribute::Tonghuan => 19,
+            Attribute::ShuiYao => 20,
+            Attribute::Yin => 21,
+            Attribute::Special => 22,
+            Attribute::None => 23,
+        };
+        for (i, attribute) in Attribute::ALL.into_iter().enumerate() {
+            assert_eq!(index(attribute), i);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 use super::attributes::{Attribute, SkillType};
+use crate::buff_effect::ExceptionEffect;
 
 /// 技能数据
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,20 +21,98 @@ pub struct Skill {
     pub max_pp: u16,
     /// 技能威力
     pub power: u16,
-    /// 是否是先手技能
-    pub is_preemptive: bool,
+    /// 技能命中率(百分比), 大于等于100时必定命中
+    pub accuracy: u8,
+    /// 技能优先级, 优先级高的技能先出手, 相同时比较速度
+    pub priority: i8,
 
     /// 技能特殊效果
     pub special_effect: Option<SkillSpecialEffect>,
 }
 
+/// 挣扎的技能ID, 精灵所有技能的PP都耗尽时使用
+pub const STRUGGLE_SKILL_ID: u64 = 0;
+
+impl Skill {
+    /// 挣扎: 无属性的物理攻击, 不消耗PP, 使用者会受到反伤
+    pub fn struggle() -> Self {
+        Self {
+            id: STRUGGLE_SKILL_ID,
+            name: "挣扎".to_string(),
+            description: "所有技能的PP都耗尽时使用, 使用者受到最大HP四分之一的反伤".to_string(),
+            skill_type: SkillType::Physical,
+            attribute: Attribute::None,
+            pp: 0,
+            max_pp: 0,
+            power: 50,
+            accuracy: 100,
+            priority: 0,
+            special_effect: None,
+        }
+    }
+}
+
 /// 技能特殊效果
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+///
+/// `chance` 为触发概率(百分比), 大于等于100时必定触发
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SkillSpecialEffect {
     /// 提升属性
-    BoostAttribute,
+    BoostAttribute {
+        stat: BattleStat,
+        /// 提升的等级数
+        stages: u8,
+        chance: u8,
+        target: EffectTarget,
+    },
     /// 降低属性
-    ReduceAttribute,
+    ReduceAttribute {
+        stat: BattleStat,
+        /// 降低的等级数
+        stages: u8,
+        chance: u8,
+        target: EffectTarget,
+    },
     /// 状态效果
-    StatusEffect,
+    StatusEffect {
+        effect: ExceptionEffect,
+        /// 持续回合数
+        duration: u8,
+        chance: u8,
+        target: EffectTarget,
+    },
+}
+
+/// 对战中可以提升或降低等级的能力
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BattleStat {
+    /// 物理攻击力
+    PhyAtk,
+    /// 物理防御力
+    PhyDef,
+    /// 法术攻击力
+    MagAtk,
+    /// 法术防御力
+    MagDef,
+    /// 速度
+    Speed,
+}
+
+impl BattleStat {
+    pub const ALL: [BattleStat; 5] = [
+        BattleStat::PhyAtk,
+        BattleStat::PhyDef,
+        BattleStat::MagAtk,
+        BattleStat::MagDef,
+        BattleStat::Speed,
+    ];
+}
+
+/// 技能特殊效果的作用对象
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EffectTarget {
+    /// 使用技能的精灵自身
+    User,
+    /// 对方上场的精灵
+    Opponent,
 }
@@ -14,13 +14,27 @@ async fn main() -> anyhow::Result<()> {
         GameMessageCodec::<ClientMessage, ServerMessage>::default(),
     );
 
+    // 注册账号, 账号已存在时注册失败, 直接登录即可
+    let msg = ClientPayload::Register {
+        username: "account".to_owned(),
+        password: "password".to_owned(),
+    };
+    let msg = ClientMessage {
+        sequence: 1,
+        payload: msg,
+    };
+    framed.send(msg).await?;
+    if let Some(msg) = framed.next().await {
+        println!("收到服务端响应: {:?}", msg?);
+    }
+
     // 向服务端发送数据
     let msg = ClientPayload::Login {
         username: "account".to_owned(),
         password: "password".to_owned(),
     };
     let msg = ClientMessage {
-        sequence: 1,
+        sequence: 2,
         payload: msg,
     };
 
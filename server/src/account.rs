@@ -0,0 +1,322 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::PathBuf,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use serde::{Deserialize, Serialize};
+
+/// 用户名长度范围
+const USERNAME_LEN: std::ops::RangeInclusive<usize> = 3..=32;
+/// 密码最小长度
+const PASSWORD_MIN_LEN: usize = 6;
+
+/// 用户名不存在时用于校验密码的哈希, 使登录耗时不会暴露用户名是否已注册
+static DUMMY_PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| {
+    hash_password(&format!("{:x}", rand::random::<u128>())).expect("生成占位密码哈希")
+});
+
+/// 玩家账号
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    /// 玩家ID
+    pub player_id: u64,
+    pub username: String,
+    /// argon2 哈希后的密码(PHC 格式字符串)
+    pub password_hash: String,
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+    /// 用户名已被注册
+    UsernameTaken,
+    /// 用户名或密码格式不合法
+    InvalidInput(String),
+    /// 用户名或密码错误
+    WrongCredentials,
+    /// 存储或哈希出错
+    Internal(String),
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::UsernameTaken => write!(f, "用户名已被注册"),
+            AccountError::InvalidInput(reason) => write!(f, "{}", reason),
+            AccountError::WrongCredentials => write!(f, "用户名或密码错误"),
+            AccountError::Internal(reason) => write!(f, "服务器内部错误: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+/// 账号存储
+pub trait AccountStore: Send + Sync {
+    /// 创建账号并分配玩家ID, 用户名已存在时返回 [`AccountError::UsernameTaken`]
+    fn create(&self, username: &str, password_hash: String) -> Result<Account, AccountError>;
+
+    /// 根据用户名查找账号
+    fn find_by_username(&self, username: &str) -> Result<Option<Account>, AccountError>;
+}
+
+/// 内存账号存储, 服务器重启后数据丢失
+#[derive(Default)]
+pub struct MemoryAccountStore {
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+impl MemoryAccountStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn create(&self, username: &str, password_hash: String) -> Result<Account, AccountError> {
+        let mut accounts = self.accounts.lock().unwrap();
+        insert_account(&mut accounts, username, password_hash)
+    }
+
+    fn find_by_username(&self, username: &str) -> Result<Option<Account>, AccountError> {
+        Ok(self.accounts.lock().unwrap().get(username).cloned())
+    }
+}
+
+/// 文件账号存储, 所有账号以 JSON 数组的形式保存在一个文件中
+pub struct FileAccountStore {
+    path: PathBuf,
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+impl FileAccountStore {
+    /// 打开账号文件, 文件不存在时创建空的账号存储
+    pub fn open(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let accounts = if path.exists() {
+            let content = fs::read_to_string(&path)?;
+            let accounts: Vec<Account> = serde_json::from_str(&content)?;
+            accounts
+                .into_iter()
+                .map(|account| (account.username.clone(), account))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            path,
+            accounts: Mutex::new(accounts),
+        })
+    }
+
+    /// 先写入临时文件再替换, 避免写入中途出错损坏账号文件
+    fn save(&self, accounts: &HashMap<String, Account>) -> anyhow::Result<()> {
+        let mut accounts: Vec<&Account> = accounts.values().collect();
+        accounts.sort_by_key(|account| account.player_id);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&accounts)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl AccountStore for FileAccountStore {
+    fn create(&self, username: &str, password_hash: String) -> Result<Account, AccountError> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let account = insert_account(&mut accounts, username, password_hash)?;
+        if let Err(e) = self.save(&accounts) {
+            accounts.remove(username);
+            return Err(AccountError::Internal(e.to_string()));
+        }
+        Ok(account)
+    }
+
+    fn find_by_username(&self, username: &str) -> Result<Option<Account>, AccountError> {
+        Ok(self.accounts.lock().unwrap().get(username).cloned())
+    }
+}
+
+/// 插入新账号, 玩家ID为当前最大ID加一
+fn insert_account(
+    accounts: &mut HashMap<String, Account>,
+    username: &str,
+    password_hash: String,
+) -> Result<Account, AccountError> {
+    if accounts.contains_key(username) {
+        return Err(AccountError::UsernameTaken);
+    }
+    let player_id = accounts
+        .values()
+        .map(|account| account.player_id)
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let account = Account {
+        player_id,
+        username: username.to_owned(),
+        password_hash,
+    };
+    accounts.insert(username.to_owned(), account.clone());
+    Ok(account)
+}
+
+/// 账号服务, 负责注册与密码登录
+///
+/// 密码使用 argon2 哈希后保存, 哈希计算比较耗时, 放在阻塞线程池中执行
+#[derive(Clone)]
+pub struct AccountService {
+    store: Arc<dyn AccountStore>,
+}
+
+impl AccountService {
+    pub fn new(store: Arc<dyn AccountStore>) -> Self {
+        // 提前生成占位哈希, 避免第一次使用不存在的用户名登录时耗时不同
+        LazyLock::force(&DUMMY_PASSWORD_HASH);
+        Self { store }
+    }
+
+    /// 注册账号, 返回新账号的玩家ID
+    pub async fn register(&self, username: String, password: String) -> Result<u64, AccountError> {
+        validate_credentials(&username, &password)?;
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || {
+            let password_hash = hash_password(&password)?;
+            store
+                .create(&username, password_hash)
+                .map(|account| account.player_id)
+        })
+        .await
+        .map_err(|e| AccountError::Internal(e.to_string()))?
+    }
+
+    /// 校验用户名密码, 返回玩家ID
+    pub async fn login(&self, username: String, password: String) -> Result<u64, AccountError> {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || {
+            let Some(account) = store.find_by_username(&username)? else {
+                // 用户名不存在时同样校验一次密码, 与用户名存在时的耗时一致
+                let _ = verify_password(&password, &DUMMY_PASSWORD_HASH);
+                return Err(AccountError::WrongCredentials);
+            };
+            verify_password(&password, &account.password_hash)?;
+            Ok(account.player_id)
+        })
+        .await
+        .map_err(|e| AccountError::Internal(e.to_string()))?
+    }
+}
+
+fn validate_credentials(username: &str, password: &str) -> Result<(), AccountError> {
+    if !USERNAME_LEN.contains(&username.chars().count()) {
+        return Err(AccountError::InvalidInput(format!(
+            "用户名长度需要在 {} 到 {} 之间",
+            USERNAME_LEN.start(),
+            USERNAME_LEN.end()
+        )));
+    }
+    if username.chars().any(char::is_whitespace) {
+        return Err(AccountError::InvalidInput(
+            "用户名不能包含空白字符".to_owned(),
+        ));
+    }
+    if password.chars().count() < PASSWORD_MIN_LEN {
+        return Err(AccountError::InvalidInput(format!(
+            "密码长度不能少于 {}",
+            PASSWORD_MIN_LEN
+        )));
+    }
+    Ok(())
+}
+
+fn hash_password(password: &str) -> Result<String, AccountError> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+        .map_err(|e| AccountError::Internal(e.to_string()))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AccountError::Internal(e.to_string()))
+}
+
+fn verify_password(password: &str, password_hash: &str) -> Result<(), AccountError> {
+    let password_hash =
+        PasswordHash::new(password_hash).map_err(|e| AccountError::Internal(e.to_string()))?;
+    Argon2::default()
+        .verify_password(password.as_bytes(), &password_hash)
+        .map_err(|_| AccountError::WrongCredentials)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_register_and_login() {
+        let service = AccountService::new(Arc::new(MemoryAccountStore::new()));
+        let player_a = service
+            .register("player_a".to_owned(), "password".to_owned())
+            .await
+            .unwrap();
+        let player_b = service
+            .register("player_b".to_owned(), "password".to_owned())
+            .await
+            .unwrap();
+        assert_ne!(player_a, player_b);
+
+        assert!(matches!(
+            service
+                .register("player_a".to_owned(), "password".to_owned())
+                .await,
+            Err(AccountError::UsernameTaken)
+        ));
+        assert_eq!(
+            service
+                .login("player_a".to_owned(), "password".to_owned())
+                .await
+                .unwrap(),
+            player_a
+        );
+        assert!(matches!(
+            service
+                .login("player_a".to_owned(), "wrong_password".to_owned())
+                .await,
+            Err(AccountError::WrongCredentials)
+        ));
+        assert!(matches!(
+            service
+                .login("nobody".to_owned(), "password".to_owned())
+                .await,
+            Err(AccountError::WrongCredentials)
+        ));
+        // 占位哈希是合法的 argon2 哈希, 不存在的用户名同样会完整地校验一次密码
+        assert!(matches!(
+            verify_password("password", &DUMMY_PASSWORD_HASH),
+            Err(AccountError::WrongCredentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_file_store_persists_accounts() {
+        let path = std::env::temp_dir().join(format!("accounts_{}.json", rand::random::<u64>()));
+        let service = AccountService::new(Arc::new(FileAccountStore::open(&path).unwrap()));
+        let player_id = service
+            .register("player_a".to_owned(), "password".to_owned())
+            .await
+            .unwrap();
+
+        // 重新打开文件, 账号仍然存在
+        let service = AccountService::new(Arc::new(FileAccountStore::open(&path).unwrap()));
+        assert_eq!(
+            service
+                .login("player_a".to_owned(), "password".to_owned())
+                .await
+                .unwrap(),
+            player_id
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
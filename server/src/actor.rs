@@ -1,23 +1,33 @@
-use tokio::sync::mpsc;
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use tokio::{
+    sync::mpsc,
+    time::{Instant, sleep_until},
+};
 
 use crate::{
+    catalog,
     events::{EventBus, ServerEvent},
-    room::RoomActorMessage,
+    room::{RoomActionEnvelope, RoomActorMessage},
     room_manager::RoomManager,
     session::SessionManager,
     status::PlayerSession,
 };
 
 use common::{
-    message::{ClientAction, ClientMessage, ClientPayload, RoomAction, ServerPayload},
-    security::validate_token,
-    sprites::{
-        Sprite,
-        attributes::{Attribute, SkillType},
-        skills::Skill,
+    message::{
+        ClientAction, ClientMessage, ClientPayload, RoomAction, RoomActionError, ServerPayload,
+    },
+    security::{
+        TokenConfig, genenrate_token, issue_refresh_token, validate_refresh_token, validate_token,
     },
+    sprites::Sprite,
 };
 
+/// 携带其他玩家 token 达到该次数后断开连接
+const MAX_AUTH_VIOLATIONS: u32 = 3;
+
 #[derive(Debug, Clone)]
 pub enum ActorMessage {
     /// 来自客户端的消息
@@ -34,6 +44,8 @@ pub enum SystemMessage {
     EnterRoom { room_id: u64, opponent_name: String },
     /// 对方逃跑了
     Escape(u64),
+    /// 战斗结束, 离开房间
+    LeaveRoom { room_id: u64 },
 }
 
 pub struct PlayerActor {
@@ -42,6 +54,7 @@ pub struct PlayerActor {
     event_bus: EventBus,
     session_manager: SessionManager,
     room_manager: RoomManager,
+    token_config: Arc<TokenConfig>,
 }
 
 impl PlayerActor {
@@ -51,6 +64,7 @@ impl PlayerActor {
         event_bus: EventBus,
         session_manager: SessionManager,
         room_manager: RoomManager,
+        token_config: Arc<TokenConfig>,
     ) -> Self {
         Self {
             session: PlayerSession::new(player_id, 0, 0),
@@ -58,71 +72,205 @@ impl PlayerActor {
             event_bus,
             session_manager,
             room_manager,
+            token_config,
         }
     }
 
     pub async fn run(&mut self) -> anyhow::Result<()> {
         println!("[PlayerActor] 启动...");
 
-        while let Some(msg) = self.receiver.recv().await {
-            match msg {
-                ActorMessage::ClientMessage(client_message) => {
-                    let ClientMessage { sequence, payload } = client_message;
-                    // 1. 校验序列号是否合法
-                    if self.check_client_sequence(sequence)
-                        && let ClientPayload::Authenticated { token, action } = payload
-                    {
-                        // 2. 校验 token 是否合法，
-                        // TODO: 无感刷新 Refresh token
-                        match validate_token(&token) {
-                            Ok(_claims) => self.handle_client_action(action).await,
-                            Err(e) => {
-                                println!(
-                                    "[PlayerActor {}] 校验 token 失败: {:?}",
-                                    self.session.player_id(),
-                                    e
-                                );
-                                self.event_bus.publish(ServerEvent::SendMessageToPlayer {
-                                    player_id: self.session.player_id(),
-                                    payload: ServerPayload::AuthFailed,
-                                });
-                            }
+        loop {
+            let refresh_at = self.token_refresh_at();
+            tokio::select! {
+                msg = self.receiver.recv() => {
+                    let Some(msg) = msg else {
+                        break;
+                    };
+                    match msg {
+                        ActorMessage::ClientMessage(client_message) => {
+                            self.handle_client_message(client_message).await;
+                        }
+                        ActorMessage::SystemNotification(system_message) => {
+                            self.handle_system_notification(system_message).await?;
+                        }
+                        ActorMessage::Disconnect => {
+                            // 会话已由连接注销
+                            println!("[PlayerActor] 收到断开连接通知");
+                            self.event_bus.publish(ServerEvent::PlayerDisconnected {
+                                player_id: self.session.player_id(),
+                            });
+                            // 断开，跳出run，该actor运行结束
+                            return Ok(());
                         }
                     }
                 }
-                ActorMessage::SystemNotification(system_message) => {
-                    self.handle_system_notification(system_message).await?;
-                }
-                ActorMessage::Disconnect => {
-                    println!("[PlayerActor] 收到断开连接通知");
-                    self.session_manager
-                        .unregister(self.session.player_id())
-                        .await;
-                    // 断开，跳出run，该actor运行结束
-                    return Ok(());
+                // token 即将过期, 主动下发新的 token
+                _ = sleep_until(refresh_at.unwrap_or_else(Instant::now)), if refresh_at.is_some() => {
+                    let player_id = self.session.player_id();
+                    // 客户端空闲超过 token 有效时间后不再续期, 避免闲置连接一直保持登录
+                    if self.session.idle_secs() > self.token_config.ttl().num_seconds() {
+                        println!("[PlayerActor {}] 客户端长时间未活动, 停止主动刷新 token", player_id);
+                        self.session.clear_token_expires_at();
+                    } else {
+                        println!("[PlayerActor {}] token 即将过期, 主动刷新", player_id);
+                        self.push_refreshed_token(None).await;
+                    }
                 }
             }
         }
         Ok(())
     }
 
+    async fn handle_client_message(&mut self, client_message: ClientMessage) {
+        let ClientMessage { sequence, payload } = client_message;
+        // 1. 校验序列号是否合法
+        if !self.check_client_sequence(sequence) {
+            return;
+        }
+        self.session.touch();
+        match payload {
+            ClientPayload::Authenticated { token, action } => {
+                // 2. 校验 token 是否合法
+                match validate_token(&self.token_config, &token) {
+                    Ok(claims) if claims.subject() != self.session.player_id() => {
+                        self.handle_auth_violation(claims.subject()).await;
+                    }
+                    Ok(claims) => {
+                        self.session.update_token_expires_at(claims.expires_at());
+                        self.handle_client_action(action).await;
+                    }
+                    Err(e) => {
+                        println!(
+                            "[PlayerActor {}] 校验 token 失败: {:?}",
+                            self.session.player_id(),
+                            e
+                        );
+                        self.send_auth_failed().await;
+                    }
+                }
+            }
+            ClientPayload::RefreshToken { refresh_token } => {
+                match validate_refresh_token(&self.token_config, &refresh_token) {
+                    Ok(claims) if claims.subject() != self.session.player_id() => {
+                        self.handle_auth_violation(claims.subject()).await;
+                    }
+                    Ok(claims) => self.push_refreshed_token(Some(claims.id())).await,
+                    Err(e) => {
+                        println!(
+                            "[PlayerActor {}] 校验 refresh token 失败: {:?}",
+                            self.session.player_id(),
+                            e
+                        );
+                        self.send_auth_failed().await;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 下一次主动刷新 token 的时间, 在 token 过期前 `refresh_before` 刷新
+    fn token_refresh_at(&self) -> Option<Instant> {
+        let expires_at = self.session.token_expires_at()? as i64;
+        let refresh_at = expires_at - self.token_config.refresh_before().num_seconds();
+        let delay = (refresh_at - Utc::now().timestamp()).max(0) as u64;
+        Some(Instant::now() + Duration::from_secs(delay))
+    }
+
+    /// 签发新的 token 与 refresh token 并推送给客户端, 之前的 refresh token 随之失效
+    ///
+    /// 客户端使用 refresh token 请求刷新时, 只接受当前有效的 refresh token
+    async fn push_refreshed_token(&mut self, used_refresh_token_id: Option<&str>) {
+        let player_id = self.session.player_id();
+        let (refresh_token, refresh_claims) = issue_refresh_token(&self.token_config, player_id);
+        if !self
+            .session_manager
+            .rotate_refresh_token(
+                player_id,
+                used_refresh_token_id,
+                refresh_claims.id().to_owned(),
+            )
+            .await
+        {
+            println!(
+                "[PlayerActor {}] refresh token 已被轮换, 拒绝使用",
+                player_id
+            );
+            self.send_auth_failed().await;
+            return;
+        }
+        let token = genenrate_token(&self.token_config, player_id);
+        let expires_at = (Utc::now() + self.token_config.ttl()).timestamp() as usize;
+        self.session.update_token_expires_at(expires_at);
+        self.session_manager
+            .send_to_player(
+                player_id,
+                ServerPayload::TokenRefreshed {
+                    token,
+                    refresh_token,
+                },
+            )
+            .await;
+    }
+
+    /// 处理携带其他玩家 token 的请求: 拒绝请求, 多次违规后断开连接
+    async fn handle_auth_violation(&mut self, token_player_id: u64) {
+        let player_id = self.session.player_id();
+        let violations = self.session.record_auth_violation();
+        println!(
+            "[PlayerActor {}] 疑似冒充: 使用了玩家 {} 的 token, 累计 {} 次",
+            player_id, token_player_id, violations
+        );
+        self.send_auth_failed().await;
+        if violations >= MAX_AUTH_VIOLATIONS {
+            println!("[PlayerActor {}] 多次冒充其他玩家, 断开连接", player_id);
+            self.session_manager.disconnect_player(player_id).await;
+        }
+    }
+
+    async fn send_auth_failed(&self) {
+        self.session_manager
+            .send_to_player(self.session.player_id(), ServerPayload::AuthFailed)
+            .await;
+    }
+
     async fn handle_client_action(&mut self, action: ClientAction) {
         match action {
             ClientAction::Chat { content } => {
                 // 处理聊天消息
-                self.event_bus.publish(ServerEvent::SendMessageToPlayer {
-                    player_id: self.session.player_id(),
-                    payload: ServerPayload::Chat {
-                        content: format!("玩家 {} 说: {}", self.session.player_id(), content),
-                    },
-                });
+                self.session_manager
+                    .send_to_player(
+                        self.session.player_id(),
+                        ServerPayload::Chat {
+                            content: format!("玩家 {} 说: {}", self.session.player_id(), content),
+                        },
+                    )
+                    .await;
             }
             ClientAction::SpriteTeam => {
                 let sprite_team = get_sprite_team(self.session.player_id()).await;
-                self.event_bus.publish(ServerEvent::SendMessageToPlayer {
-                    player_id: self.session.player_id(),
-                    payload: ServerPayload::SpriteTeam(sprite_team),
-                });
+                self.session_manager
+                    .send_to_player(
+                        self.session.player_id(),
+                        ServerPayload::SpriteTeam(sprite_team),
+                    )
+                    .await;
+            }
+            ClientAction::StartMatchmaking { mode } => {
+                // 已经在房间中的玩家不能再次匹配
+                if let Some(room_id) = self.session.room_id() {
+                    println!(
+                        "[PlayerActor {}] 已经在房间 {} 中, 忽略匹配请求",
+                        self.session.player_id(),
+                        room_id
+                    );
+                    return;
+                }
+                self.event_bus
+                    .publish(ServerEvent::PlayerReadyForMatchmaking {
+                        player_id: self.session.player_id(),
+                        mode,
+                    });
             }
             ClientAction::RoomAction(room_action) => {
                 self.handle_room_action(room_action).await;
@@ -131,35 +279,51 @@ impl PlayerActor {
         }
     }
 
+    /// 将玩家提交的房间操作转发给所在的房间
     async fn handle_room_action(&mut self, room_action: RoomAction) {
-        match room_action {
-            RoomAction::SkillAttack { skill_id, .. } => {
-                let Some(room_id) = self.session.room_id() else {
-                    return;
-                };
-                let Some(room_sender) = self.room_manager.get_room_sender(room_id).await else {
-                    return;
-                };
-                // 获取该技能
-
-                if let Err(e) = room_sender
-                    .send(RoomActorMessage::RoomAction(RoomAction::SkillAttack {
-                        player_id: self.session.player_id(),
-                        skill_id,
-                    }))
-                    .await
-                {
-                    println!(
-                        "[PlayerActor {}] 发送技能攻击消息失败: {:?}",
-                        self.session.player_id(),
-                        e
-                    );
-                }
-            }
-            _ => {}
+        let player_id = self.session.player_id();
+        let Some(room_id) = self.session.room_id() else {
+            self.reject_room_action(room_action, RoomActionError::NotInRoom)
+                .await;
+            return;
+        };
+        let Some(room_sender) = self.room_manager.get_room_sender(room_id).await else {
+            self.reject_room_action(room_action, RoomActionError::RoomClosed)
+                .await;
+            return;
+        };
+        // 使用会话中已认证的玩家ID标记操作的发起者
+        if let Err(e) = room_sender
+            .send(RoomActorMessage::RoomAction(RoomActionEnvelope {
+                player_id,
+                action: room_action,
+            }))
+            .await
+        {
+            println!(
+                "[PlayerActor {}] 发送房间操作 {:?} 失败: {:?}",
+                player_id, room_action, e
+            );
+            self.reject_room_action(room_action, RoomActionError::RoomClosed)
+                .await;
         }
     }
 
+    async fn reject_room_action(&self, action: RoomAction, reason: RoomActionError) {
+        println!(
+            "[PlayerActor {}] 房间操作 {:?} 被拒绝: {:?}",
+            self.session.player_id(),
+            action,
+            reason
+        );
+        self.session_manager
+            .send_to_player(
+                self.session.player_id(),
+                ServerPayload::RoomActionRejected { action, reason },
+            )
+            .await;
+    }
+
     async fn handle_system_notification(
         &mut self,
         system_message: SystemMessage,
@@ -187,6 +351,17 @@ impl PlayerActor {
                         .await?;
                 }
             }
+            SystemMessage::LeaveRoom { room_id } => {
+                // 只清除仍然是该房间的房间ID, 避免清除新加入的房间
+                if self.session.room_id() == Some(room_id) {
+                    println!(
+                        "[PlayerActor {}] 离开房间 {}",
+                        self.session.player_id(),
+                        room_id
+                    );
+                    self.session.clear_room_id();
+                }
+            }
             SystemMessage::Escape(escaped_player_id) => {
                 if escaped_player_id == self.session.player_id() {
                     // 自己逃跑了，关闭房间
@@ -221,81 +396,19 @@ impl PlayerActor {
     }
 }
 
-// TODO:
 /// 从数据库中获取玩家的精灵队伍
 async fn get_sprite_team(player_id: u64) -> Vec<Sprite> {
-    // TODO:从数据库中加载
-    let mut preset_team_ids = Vec::with_capacity(6);
-    // 临时模拟数据
-    for i in 0..6 {
-        let hp = 350 + rand::random::<u8>() as u16 % 101;
-        let sprite = Sprite {
-            id: i,
-            level: 100,
-            exp: 9999,
-            max_exp: 10000,
-            hp,
-            max_hp: hp,
-            phy_atk: 350 + rand::random::<u8>() as u16 % 51,
-            phy_def: 240 + rand::random::<u8>() as u16 % 51,
-            mag_atk: 340 + rand::random::<u8>() as u16 % 51,
-            mag_def: 230 + rand::random::<u8>() as u16 % 51,
-            speed: 300 + rand::random::<u8>() as u16 % 101,
-
-            skills: vec![
-                Skill {
-                    id: 1,
-                    name: "普通攻击".to_string(),
-                    description: "普通物理攻击".to_string(),
-                    skill_type: SkillType::Physical,
-                    attribute: Attribute::Huo,
-                    pp: 10,
-                    max_pp: 10,
-                    power: 100,
-                    special_effect: None,
-                    is_preemptive: false,
-                },
-                Skill {
-                    id: 2,
-                    name: "普通攻击".to_string(),
-                    description: "普通物理攻击".to_string(),
-                    skill_type: SkillType::Physical,
-                    attribute: Attribute::Huo,
-                    pp: 10,
-                    max_pp: 10,
-                    power: 100,
-                    special_effect: None,
-                    is_preemptive: false,
-                },
-            ],
-        };
-        preset_team_ids.push(sprite);
+    // TODO:从数据库中加载, 暂时按玩家ID轮换选取图鉴中的6种精灵
+    let species: Vec<_> = catalog::catalog().all_species().collect();
+    if species.is_empty() {
+        return Vec::new();
     }
-    preset_team_ids
-}
-
-/// 从技能配置数据库中获取技能配置
-///
-/// # Arguments
-///
-/// * `skill_id` - 技能ID
-///
-/// # Returns
-///
-/// * `Skill` - 技能配置
-async fn get_skill(skill_id: u64) -> Skill {
-    // TODO:从数据库中加载
-    let skill = Skill {
-        id: skill_id,
-        name: format!("技能{}", skill_id),
-        description: format!("这是技能{}的描述", skill_id),
-        skill_type: SkillType::Physical,
-        attribute: Attribute::Huo,
-        pp: 10,
-        max_pp: 10,
-        power: 100,
-        special_effect: None,
-        is_preemptive: false,
-    };
-    skill
+    let offset = (player_id % species.len() as u64) as usize;
+    species
+        .iter()
+        .cycle()
+        .skip(offset)
+        .take(species.len().min(6))
+        .map(|species| species.create_sprite(catalog::MAX_LEVEL, catalog::catalog()))
+        .collect()
 }
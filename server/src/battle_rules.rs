@@ -0,0 +1,244 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::LazyLock,
+    time::Duration,
+};
+
+use common::message::MatchMode;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, ConfigProblems};
+
+/// 各对战模式的规则的配置文件
+const CONFIG_FILE: &str = "battle_rules.json";
+/// 离线工具读取的单个对战规则
+const RULES_FILE: &str = "对战规则文件";
+
+/// 各对战模式的对战规则
+pub type BattleRulesConfig = HashMap<MatchMode, BattleRules>;
+
+static BATTLE_RULES: LazyLock<Result<BattleRulesConfig, ConfigProblems>> = LazyLock::new(|| {
+    // 从配置目录读取配置文件, 解析并校验
+    config::read(CONFIG_FILE).and_then(|config| parse_battle_rules(&config))
+});
+
+/// 对战规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BattleRules {
+    /// 等待双方提交精灵队伍的时间(秒)
+    pub team_timeout_secs: u64,
+    /// 每回合等待提交行为的时间(秒)
+    pub turn_timeout_secs: u64,
+    /// 等待替换倒下精灵的时间(秒)
+    pub replacement_timeout_secs: u64,
+    /// 房间检查超时的间隔(毫秒)
+    pub tick_millis: u64,
+    /// 出战精灵的数量上限
+    pub team_size: usize,
+    /// 出战精灵的等级上限
+    pub level_cap: u8,
+    /// 是否允许使用道具
+    pub allow_items: bool,
+    /// 切换精灵是否占用本回合的行动
+    pub switch_costs_turn: bool,
+    /// 回合数上限, 达到上限时按 `tie_break` 判定胜负
+    pub max_turns: u32,
+    /// 达到回合数上限时的胜负判定
+    pub tie_break: TieBreak,
+    /// 连续超时未提交行为达到该回合数时判负
+    pub max_afk_turns: u32,
+}
+
+/// 达到回合数上限时的胜负判定规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TieBreak {
+    /// 直接平局
+    Draw,
+    /// 剩余可以战斗的精灵多的一方获胜
+    RemainingSprites,
+    /// 剩余HP比例高的一方获胜
+    RemainingHp,
+}
+
+impl Default for BattleRules {
+    fn default() -> Self {
+        Self {
+            team_timeout_secs: 60,
+            turn_timeout_secs: 10,
+            replacement_timeout_secs: 10,
+            tick_millis: 1000,
+            team_size: 6,
+            level_cap: 100,
+            allow_items: true,
+            switch_costs_turn: true,
+            max_turns: 100,
+            tie_break: TieBreak::Draw,
+            max_afk_turns: 3,
+        }
+    }
+}
+
+impl BattleRules {
+    pub fn team_timeout(&self) -> Duration {
+        Duration::from_secs(self.team_timeout_secs)
+    }
+
+    pub fn turn_timeout(&self) -> Duration {
+        Duration::from_secs(self.turn_timeout_secs)
+    }
+
+    pub fn replacement_timeout(&self) -> Duration {
+        Duration::from_secs(self.replacement_timeout_secs)
+    }
+
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_millis)
+    }
+
+    /// 校验规则的取值, 返回发现的所有问题
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (name, value) in [
+            ("team_timeout_secs", self.team_timeout_secs),
+            ("turn_timeout_secs", self.turn_timeout_secs),
+            ("replacement_timeout_secs", self.replacement_timeout_secs),
+            ("tick_millis", self.tick_millis),
+        ] {
+            if value == 0 {
+                problems.push(format!("{} 必须大于0", name));
+            }
+        }
+        if !(1..=6).contains(&self.team_size) {
+            problems.push(format!("team_size {} 必须在1到6之间", self.team_size));
+        }
+        if self.level_cap == 0 {
+            problems.push("level_cap 必须大于0".to_string());
+        }
+        if self.max_turns == 0 {
+            problems.push("max_turns 必须大于0".to_string());
+        }
+        if self.max_afk_turns == 0 {
+            problems.push("max_afk_turns 必须大于0".to_string());
+        }
+        problems
+    }
+}
+
+/// 校验每个对战模式都配置了合法的规则
+pub fn check_battle_rules() -> Result<(), ConfigProblems> {
+    config::check(&BATTLE_RULES)
+}
+
+/// 解析各对战模式的规则, 检查:
+/// * 未知的对战模式
+/// * 缺少字段或字段类型错误
+/// * 不合法的取值, 如超时时间为0
+/// * 缺少某个对战模式的规则, 配置需要覆盖 [`MatchMode`] 的所有变体
+pub fn parse_battle_rules(config: &str) -> Result<BattleRulesConfig, ConfigProblems> {
+    let raw: BTreeMap<String, serde_json::Value> = serde_json::from_str(config)
+        .map_err(|e| ConfigProblems::new(CONFIG_FILE, vec![format!("JSON 格式错误: {}", e)]))?;
+
+    let mut problems = Vec::new();
+    let mut battle_rules = BattleRulesConfig::new();
+    let mut configured_modes = Vec::new();
+    for (mode_name, value) in raw {
+        let Some(mode) = parse_match_mode(&mode_name) else {
+            problems.push(format!("未知的对战模式 \"{}\"", mode_name));
+            continue;
+        };
+        configured_modes.push(mode);
+        let rules: BattleRules = match serde_json::from_value(value) {
+            Ok(rules) => rules,
+            Err(e) => {
+                problems.push(format!("对战模式 {:?} 的规则格式错误: {}", mode, e));
+                continue;
+            }
+        };
+        let rule_problems = rules.problems();
+        if rule_problems.is_empty() {
+            battle_rules.insert(mode, rules);
+        } else {
+            problems.extend(
+                rule_problems
+                    .into_iter()
+                    .map(|problem| format!("对战模式 {:?} 的 {}", mode, problem)),
+            );
+        }
+    }
+
+    for mode in MatchMode::ALL {
+        if !configured_modes.contains(&mode) {
+            problems.push(format!("缺少对战模式 {:?} 的规则", mode));
+        }
+    }
+
+    ConfigProblems::check(CONFIG_FILE, battle_rules, problems)
+}
+
+/// 解析单个对战规则, 用于离线工具读取独立的规则文件
+pub fn parse_rules(config: &str) -> Result<BattleRules, ConfigProblems> {
+    let rules: BattleRules = serde_json::from_str(config)
+        .map_err(|e| ConfigProblems::new(RULES_FILE, vec![format!("规则格式错误: {}", e)]))?;
+    let problems = rules.problems();
+    ConfigProblems::check(RULES_FILE, rules, problems)
+}
+
+/// 获取对战模式的规则
+pub fn get_battle_rules(mode: MatchMode) -> BattleRules {
+    config::get(&BATTLE_RULES)
+        .get(&mode)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// 按配置中的名称解析对战模式, 名称与 [`MatchMode`] 的变体名一致
+fn parse_match_mode(name: &str) -> Option<MatchMode> {
+    serde_json::from_value(serde_json::Value::String(name.to_owned())).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_battle_rules() {
+        assert!(!get_battle_rules(MatchMode::Ranked).allow_items);
+        assert!(!get_battle_rules(MatchMode::Friendly).switch_costs_turn);
+    }
+
+    #[test]
+    fn test_parse_battle_rules() {
+        let mut ranked = serde_json::to_value(BattleRules::default()).unwrap();
+        ranked["turn_timeout_secs"] = 0.into();
+        ranked["team_size"] = 7.into();
+        let config = serde_json::json!({
+            "Ranked": ranked,
+            "Casual": { "team_size": 6 },
+            "Arena": BattleRules::default(),
+        })
+        .to_string();
+        let error = parse_battle_rules(&config).unwrap_err();
+        assert!(
+            error
+                .problems
+                .contains(&"未知的对战模式 \"Arena\"".to_string())
+        );
+        assert!(
+            error
+                .problems
+                .contains(&"对战模式 Ranked 的 turn_timeout_secs 必须大于0".to_string())
+        );
+        assert!(error.problems.iter().any(|p| p.contains("team_size 7")));
+        assert!(
+            error
+                .problems
+                .iter()
+                .any(|p| p.starts_with("对战模式 Casual 的规则格式错误"))
+        );
+        assert!(
+            error
+                .problems
+                .contains(&"缺少对战模式 Friendly 的规则".to_string())
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! 无网络的战斗模拟器, 用于平衡性调整
+//!
+//! 用法: `battle-sim <队伍A.json> <队伍B.json> [--rules <规则.json>] [--games <局数>] [--seed <种子>] [--policy-a <策略>] [--policy-b <策略>]`
+//!
+//! 队伍文件为精灵数组, 规则文件为单个 `BattleRules`, 未指定规则时使用排位模式的规则。
+//! 策略可选 `first`、`strongest`、`random`, 默认为 `random`
+
+use std::path::Path;
+
+use anyhow::Context;
+use common::{message::MatchMode, sprites::Sprite};
+use server::{
+    battle_rules::{self, BattleRules},
+    simulator::{self, Policy},
+};
+
+struct Args {
+    teams: [String; 2],
+    rules: Option<String>,
+    games: u32,
+    seed: u64,
+    policies: [Policy; 2],
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut teams = Vec::new();
+    let mut rules = None;
+    let mut games = 100;
+    let mut seed = rand::random();
+    let mut policies = [Policy::Random; 2];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("参数 {} 缺少取值", arg))
+        };
+        match arg.as_str() {
+            "--rules" => rules = Some(value()?),
+            "--games" => games = value()?.parse().context("--games 必须是整数")?,
+            "--seed" => seed = value()?.parse().context("--seed 必须是整数")?,
+            "--policy-a" => policies[0] = value()?.parse()?,
+            "--policy-b" => policies[1] = value()?.parse()?,
+            _ => teams.push(arg),
+        }
+    }
+
+    let teams: [String; 2] = teams
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("需要且只需要两个队伍文件"))?;
+    Ok(Args {
+        teams,
+        rules,
+        games,
+        seed,
+        policies,
+    })
+}
+
+fn load_team(path: &Path) -> anyhow::Result<Vec<Sprite>> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("读取队伍文件 {:?} 失败", path))?;
+    serde_json::from_str(&content).with_context(|| format!("解析队伍文件 {:?} 失败", path))
+}
+
+fn load_rules(path: Option<&str>) -> anyhow::Result<BattleRules> {
+    let Some(path) = path else {
+        return Ok(battle_rules::get_battle_rules(MatchMode::Ranked));
+    };
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("读取规则文件 {} 失败", path))?;
+    Ok(battle_rules::parse_rules(&content)?)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    let team_a = load_team(Path::new(&args.teams[0]))?;
+    let team_b = load_team(Path::new(&args.teams[1]))?;
+    let rules = load_rules(args.rules.as_deref())?;
+
+    println!(
+        "模拟 {} 局, 随机数种子: {}, 策略: {:?}",
+        args.games, args.seed, args.policies
+    );
+    let report = simulator::simulate(
+        [&team_a, &team_b],
+        rules,
+        args.policies,
+        args.games,
+        args.seed,
+    );
+    print!("{}", report);
+    Ok(())
+}
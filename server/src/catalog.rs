@@ -0,0 +1,404 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::LazyLock,
+};
+
+use common::sprites::{
+    Sprite,
+    attributes::{Attribute, SkillType},
+    skills::{STRUGGLE_SKILL_ID, Skill, SkillSpecialEffect},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, ConfigProblems};
+
+/// 图鉴的配置文件
+const CONFIG_FILES: &str = "skills.json/species.json";
+
+/// 精灵最多携带的技能数量
+pub const MAX_SKILLS: usize = 4;
+/// 精灵的最高等级
+pub const MAX_LEVEL: u8 = 100;
+/// 种族基础能力值的上限, 保证满级精灵的能力值不会超出 `u16`
+pub const MAX_BASE_STAT: u16 = 255;
+
+static CATALOG: LazyLock<Result<Catalog, ConfigProblems>> = LazyLock::new(|| {
+    // 从配置目录读取配置文件, 两个文件都读取成功后再解析并校验
+    let skills = config::read("skills.json");
+    let species = config::read("species.json");
+    match (skills, species) {
+        (Ok(skills), Ok(species)) => parse_catalog(&skills, &species),
+        (skills, species) => Err(ConfigProblems::new(
+            CONFIG_FILES,
+            [skills.err(), species.err()]
+                .into_iter()
+                .flatten()
+                .flat_map(|error| error.problems)
+                .collect(),
+        )),
+    }
+});
+
+/// 技能配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkillConfig {
+    pub id: u64,
+    /// 技能名称
+    pub name: String,
+    /// 技能描述
+    pub description: String,
+    /// 技能类型
+    pub skill_type: SkillType,
+    /// 技能属性
+    pub attribute: Attribute,
+    /// 技能最大PP值
+    pub pp: u16,
+    /// 技能威力
+    pub power: u16,
+    /// 技能命中率(百分比)
+    pub accuracy: u8,
+    /// 技能优先级
+    pub priority: i8,
+    /// 技能特殊效果
+    pub special_effect: Option<SkillSpecialEffect>,
+}
+
+impl SkillConfig {
+    /// 创建PP全满的技能
+    pub fn to_skill(&self) -> Skill {
+        Skill {
+            id: self.id,
+            name: self.name.clone(),
+            description: self.description.clone(),
+            skill_type: self.skill_type,
+            attribute: self.attribute,
+            pp: self.pp,
+            max_pp: self.pp,
+            power: self.power,
+            accuracy: self.accuracy,
+            priority: self.priority,
+            special_effect: self.special_effect,
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.id == STRUGGLE_SKILL_ID {
+            problems.push(format!("技能ID {} 保留给挣扎使用", STRUGGLE_SKILL_ID));
+        }
+        if self.pp == 0 {
+            problems.push("pp 必须大于0".to_string());
+        }
+        if self.accuracy == 0 {
+            problems.push("accuracy 必须大于0".to_string());
+        }
+        let (chance, stages) = match self.special_effect {
+            Some(SkillSpecialEffect::BoostAttribute { chance, stages, .. })
+            | Some(SkillSpecialEffect::ReduceAttribute { chance, stages, .. }) => (chance, stages),
+            Some(SkillSpecialEffect::StatusEffect {
+                chance, duration, ..
+            }) => (chance, duration),
+            None => (1, 1),
+        };
+        if chance == 0 {
+            problems.push("特殊效果的触发概率必须大于0".to_string());
+        }
+        if stages == 0 {
+            problems.push("特殊效果的等级数或持续回合数必须大于0".to_string());
+        }
+        problems
+    }
+}
+
+/// 精灵种族的基础能力值
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaseStats {
+    pub hp: u16,
+    pub phy_atk: u16,
+    pub phy_def: u16,
+    pub mag_atk: u16,
+    pub mag_def: u16,
+    pub speed: u16,
+}
+
+/// 精灵种族配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Species {
+    pub id: u64,
+    /// 种族名称
+    pub name: String,
+    /// 种族属性, 一到两个
+    pub attributes: Vec<Attribute>,
+    pub base_stats: BaseStats,
+    /// 可以学会的技能ID, 按学习顺序排列
+    pub learnset: Vec<u64>,
+}
+
+impl Species {
+    /// 按等级计算能力值, 创建携带前 [`MAX_SKILLS`] 个可学技能的精灵
+    ///
+    /// 等级限制在 1 到 [`MAX_LEVEL`] 之间
+    pub fn create_sprite(&self, level: u8, catalog: &Catalog) -> Sprite {
+        let level = level.clamp(1, MAX_LEVEL);
+        let saturate = |value: u32| u16::try_from(value).unwrap_or(u16::MAX);
+        let stat = |base: u16| saturate(base as u32 * 2 * level as u32 / 100 + 5);
+        let hp = saturate(self.base_stats.hp as u32 * 2 * level as u32 / 100 + level as u32 + 10);
+        Sprite {
+            id: self.id,
+            attributes: self.attributes.clone(),
+            level,
+            exp: 0,
+            max_exp: level as u32 * level as u32 * level as u32,
+            hp,
+            max_hp: hp,
+            phy_atk: stat(self.base_stats.phy_atk),
+            phy_def: stat(self.base_stats.phy_def),
+            mag_atk: stat(self.base_stats.mag_atk),
+            mag_def: stat(self.base_stats.mag_def),
+            speed: stat(self.base_stats.speed),
+            skills: self
+                .learnset
+                .iter()
+                .filter_map(|skill_id| catalog.skills.get(skill_id))
+                .take(MAX_SKILLS)
+                .map(SkillConfig::to_skill)
+                .collect(),
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !(1..=2).contains(&self.attributes.len()) {
+            problems.push(format!(
+                "attributes 必须有一到两个属性, 实际为 {}",
+                self.attributes.len()
+            ));
+        } else if self.attributes.len() == 2 && self.attributes[0] == self.attributes[1] {
+            problems.push(format!("attributes 中的属性 {:?} 重复", self.attributes[0]));
+        }
+        let stats = self.base_stats;
+        for (name, value) in [
+            ("hp", stats.hp),
+            ("phy_atk", stats.phy_atk),
+            ("phy_def", stats.phy_def),
+            ("mag_atk", stats.mag_atk),
+            ("mag_def", stats.mag_def),
+            ("speed", stats.speed),
+        ] {
+            if value == 0 {
+                problems.push(format!("基础能力值 {} 必须大于0", name));
+            } else if value > MAX_BASE_STAT {
+                problems.push(format!(
+                    "基础能力值 {} 不能超过 {}, 实际为 {}",
+                    name, MAX_BASE_STAT, value
+                ));
+            }
+        }
+        if self.learnset.is_empty() {
+            problems.push("learnset 不能为空".to_string());
+        }
+        problems
+    }
+}
+
+/// 精灵种族与技能图鉴
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    skills: BTreeMap<u64, SkillConfig>,
+    species: BTreeMap<u64, Species>,
+}
+
+impl Catalog {
+    pub fn skill(&self, skill_id: u64) -> Option<&SkillConfig> {
+        self.skills.get(&skill_id)
+    }
+
+    pub fn species(&self, species_id: u64) -> Option<&Species> {
+        self.species.get(&species_id)
+    }
+
+    /// 按ID顺序遍历所有种族
+    pub fn all_species(&self) -> impl Iterator<Item = &Species> {
+        self.species.values()
+    }
+}
+
+/// 校验技能与种族图鉴, 包括种族引用的技能是否存在
+pub fn check_catalog() -> Result<(), ConfigProblems> {
+    config::check(&CATALOG)
+}
+
+/// 解析技能与种族图鉴, 种族的可学技能需要在技能图鉴中, 所以两个文件一起解析, 检查:
+/// * 缺少字段或字段类型错误
+/// * 重复的技能ID或种族ID
+/// * 不合法的取值, 如PP为0、属性数量不是一到两个
+/// * 种族的可学技能引用了不存在的技能
+pub fn parse_catalog(skills: &str, species: &str) -> Result<Catalog, ConfigProblems> {
+    let mut problems = Vec::new();
+    let mut catalog = Catalog::default();
+
+    for value in parse_entries("skills.json", skills, &mut problems) {
+        let skill: SkillConfig = match serde_json::from_value(value) {
+            Ok(skill) => skill,
+            Err(e) => {
+                problems.push(format!("skills.json 中的技能格式错误: {}", e));
+                continue;
+            }
+        };
+        if catalog.skills.contains_key(&skill.id) {
+            problems.push(format!("技能ID {} 重复", skill.id));
+            continue;
+        }
+        problems.extend(
+            skill
+                .problems()
+                .into_iter()
+                .map(|problem| format!("技能 {} 的 {}", skill.id, problem)),
+        );
+        catalog.skills.insert(skill.id, skill);
+    }
+
+    for value in parse_entries("species.json", species, &mut problems) {
+        let species: Species = match serde_json::from_value(value) {
+            Ok(species) => species,
+            Err(e) => {
+                problems.push(format!("species.json 中的种族格式错误: {}", e));
+                continue;
+            }
+        };
+        if catalog.species.contains_key(&species.id) {
+            problems.push(format!("种族ID {} 重复", species.id));
+            continue;
+        }
+        let mut species_problems = species.problems();
+        let mut learned = HashSet::new();
+        for skill_id in &species.learnset {
+            if !catalog.skills.contains_key(skill_id) {
+                species_problems.push(format!("learnset 引用了不存在的技能 {}", skill_id));
+            } else if !learned.insert(*skill_id) {
+                species_problems.push(format!("learnset 中的技能 {} 重复", skill_id));
+            }
+        }
+        problems.extend(
+            species_problems
+                .into_iter()
+                .map(|problem| format!("种族 {} 的 {}", species.id, problem)),
+        );
+        catalog.species.insert(species.id, species);
+    }
+
+    ConfigProblems::check(CONFIG_FILES, catalog, problems)
+}
+
+/// 解析配置文件中的条目数组, 格式错误时记录问题并返回空
+fn parse_entries(
+    file_name: &str,
+    config: &str,
+    problems: &mut Vec<String>,
+) -> Vec<serde_json::Value> {
+    serde_json::from_str(config).unwrap_or_else(|e| {
+        problems.push(format!("{} JSON 格式错误: {}", file_name, e));
+        Vec::new()
+    })
+}
+
+/// 获取图鉴
+pub fn catalog() -> &'static Catalog {
+    config::get(&CATALOG)
+}
+
+/// 获取PP全满的技能
+pub fn get_skill(skill_id: u64) -> Option<Skill> {
+    catalog().skill(skill_id).map(SkillConfig::to_skill)
+}
+
+/// 获取精灵种族
+pub fn get_species(species_id: u64) -> Option<&'static Species> {
+    catalog().species(species_id)
+}
+
+/// 按种族与等级创建精灵
+pub fn create_sprite(species_id: u64, level: u8) -> Option<Sprite> {
+    get_species(species_id).map(|species| species.create_sprite(level, catalog()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_create_sprite() {
+        assert!(catalog().all_species().count() >= 6);
+        assert_eq!(get_skill(2).unwrap().priority, 1);
+
+        let sprite = create_sprite(1, 100).unwrap();
+        assert_eq!(sprite.id, 1);
+        assert_eq!(sprite.hp, sprite.max_hp);
+        // 170 * 2 * 100 / 100 + 100 + 10
+        assert_eq!(sprite.max_hp, 450);
+        // 160 * 2 * 100 / 100 + 5
+        assert_eq!(sprite.speed, 325);
+        assert!(!sprite.skills.is_empty() && sprite.skills.len() <= MAX_SKILLS);
+        assert!(sprite.skills.iter().all(|skill| skill.pp == skill.max_pp));
+
+        // 超出范围的等级按最高等级计算
+        let sprite = create_sprite(1, u8::MAX).unwrap();
+        assert_eq!(sprite.level, MAX_LEVEL);
+        assert_eq!(sprite.max_hp, 450);
+    }
+
+    #[test]
+    fn test_parse_catalog() {
+        let skills = r#"[
+            { "id": 1, "name": "撞击", "description": "", "skill_type": "Physical", "attribute": "None",
+              "pp": 0, "power": 40, "accuracy": 100, "priority": 0, "special_effect": null },
+            { "id": 1, "name": "重复", "description": "", "skill_type": "Physical", "attribute": "None",
+              "pp": 10, "power": 40, "accuracy": 100, "priority": 0, "special_effect": null },
+            { "id": 2, "name": "缺少字段" }
+        ]"#;
+        let species = r#"[
+            { "id": 1, "name": "三属性", "attributes": ["Huo", "Shui", "Mu"],
+              "base_stats": { "hp": 100, "phy_atk": 60000, "phy_def": 100, "mag_atk": 100, "mag_def": 100, "speed": 0 },
+              "learnset": [1, 1, 3] }
+        ]"#;
+        let error = parse_catalog(skills, species).unwrap_err();
+        assert!(
+            error
+                .problems
+                .contains(&"技能 1 的 pp 必须大于0".to_string())
+        );
+        assert!(error.problems.contains(&"技能ID 1 重复".to_string()));
+        assert!(
+            error
+                .problems
+                .iter()
+                .any(|p| p.starts_with("skills.json 中的技能格式错误"))
+        );
+        assert!(
+            error
+                .problems
+                .iter()
+                .any(|p| p.starts_with("种族 1 的 attributes 必须有一到两个属性"))
+        );
+        assert!(
+            error
+                .problems
+                .contains(&"种族 1 的 基础能力值 speed 必须大于0".to_string())
+        );
+        assert!(
+            error
+                .problems
+                .contains(&"种族 1 的 基础能力值 phy_atk 不能超过 255, 实际为 60000".to_string())
+        );
+        assert!(
+            error
+                .problems
+                .contains(&"种族 1 的 learnset 中的技能 1 重复".to_string())
+        );
+        assert!(
+            error
+                .problems
+                .contains(&"种族 1 的 learnset 引用了不存在的技能 3".to_string())
+        );
+    }
+}
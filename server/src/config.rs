@@ -0,0 +1,107 @@
+use std::{fmt, path::PathBuf, sync::LazyLock};
+
+/// 配置文件校验失败, 包含配置中发现的所有问题
+#[derive(Debug, Clone)]
+pub struct ConfigProblems {
+    /// 出问题的配置文件
+    pub file: String,
+    pub problems: Vec<String>,
+}
+
+impl ConfigProblems {
+    pub fn new(file: impl Into<String>, problems: Vec<String>) -> Self {
+        Self {
+            file: file.into(),
+            problems,
+        }
+    }
+
+    /// 没有发现问题时返回解析结果, 否则返回所有问题
+    pub fn check<T>(
+        file: impl Into<String>,
+        value: T,
+        problems: Vec<String>,
+    ) -> Result<T, ConfigProblems> {
+        if problems.is_empty() {
+            Ok(value)
+        } else {
+            Err(Self::new(file, problems))
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "配置 {} 校验失败, 共 {} 个问题:",
+            self.file,
+            self.problems.len()
+        )?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigProblems {}
+
+/// 配置目录
+///
+/// 由环境变量 `CONFIG_DIR` 指定, 未设置时使用源码中的 `server/configs`。
+/// 配置在第一次使用时读取, 修改配置后重启服务器即可生效, 不需要重新编译
+pub fn config_dir() -> PathBuf {
+    std::env::var_os("CONFIG_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/configs")))
+}
+
+/// 读取配置目录中的配置文件, 读取失败时作为配置的问题返回
+pub fn read(file: &str) -> Result<String, ConfigProblems> {
+    let path = config_dir().join(file);
+    std::fs::read_to_string(&path).map_err(|e| {
+        ConfigProblems::new(
+            file,
+            vec![format!("读取配置文件 {} 失败: {}", path.display(), e)],
+        )
+    })
+}
+
+/// 提前加载配置, 配置有误时返回所有问题
+pub fn check<T>(config: &LazyLock<Result<T, ConfigProblems>>) -> Result<(), ConfigProblems> {
+    config.as_ref().map(|_| ()).map_err(Clone::clone)
+}
+
+/// 获取已加载的配置, 配置有误时 panic, 服务器启动时已经通过 [`check`] 校验
+pub fn get<T>(config: &'static LazyLock<Result<T, ConfigProblems>>) -> &'static T {
+    config.as_ref().unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{battle_rules, catalog, game_rule};
+
+    use super::*;
+
+    #[test]
+    fn test_builtin_configs_are_valid() {
+        game_rule::check_attribute_relationship().unwrap();
+        battle_rules::check_battle_rules().unwrap();
+        catalog::check_catalog().unwrap();
+    }
+
+    #[test]
+    fn test_display_all_problems() {
+        let error = ConfigProblems::check(
+            "battle_rules.json",
+            (),
+            vec!["问题一".to_string(), "问题二".to_string()],
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "配置 battle_rules.json 校验失败, 共 2 个问题:\n  - 问题一\n  - 问题二"
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use common::message::MatchMode;
+
 use crate::{
     actor::{ActorMessage, SystemMessage},
     events::{EventBus, ServerEvent},
@@ -31,29 +33,44 @@ impl GameCoordinator {
         let mut recv = self.event_bus.subscribe();
         while let Ok(event) = recv.recv().await {
             match event {
-                ServerEvent::MatchFound { players } => {
-                    println!("接收到 MatchFound 事件 player {:?}", players);
-                    self.handle_match(players).await;
+                ServerEvent::MatchFound { players, mode } => {
+                    println!(
+                        "接收到 MatchFound 事件 player {:?} 模式 {:?}",
+                        players, mode
+                    );
+                    self.handle_match(players, mode).await;
                 }
                 ServerEvent::CloseRoom { room_id } => {
                     self.handle_close_room(room_id).await;
                 }
+                ServerEvent::BattleFinished {
+                    room_id,
+                    players,
+                    result,
+                } => {
+                    println!("房间 {} 战斗结束: {:?}", room_id, result);
+                    self.handle_battle_finished(room_id, players).await;
+                }
 
                 _ => {}
             }
         }
     }
 
-    async fn handle_match(&self, players: [u64; 2]) {
+    async fn handle_match(&self, players: [u64; 2], mode: MatchMode) {
         let room_id = self
             .room_manager
             .create_room(
                 players,
+                mode,
                 self.event_bus.clone(),
                 self.session_manager.clone(),
             )
             .await;
-        println!("创建房间 {} 成功", room_id);
+        // 记录房间的随机数种子, 用于复现战斗
+        if let Some(info) = self.room_manager.get_room_info(room_id).await {
+            println!("创建房间 {} 成功, 随机数种子: {}", room_id, info.seed);
+        }
 
         for player_id in &players {
             if let Some(player_handle) = self.session_manager.get_session(*player_id).await {
@@ -80,6 +97,19 @@ impl GameCoordinator {
             .publish(ServerEvent::RoomCreated { room_id, players });
     }
 
+    /// 战斗结束后回收房间, 并通知双方玩家离开房间
+    async fn handle_battle_finished(&self, room_id: u64, players: [u64; 2]) {
+        self.room_manager.remove_finished_room(room_id).await;
+        for player_id in players {
+            self.session_manager
+                .send_message(
+                    player_id,
+                    ActorMessage::SystemNotification(SystemMessage::LeaveRoom { room_id }),
+                )
+                .await;
+        }
+    }
+
     async fn handle_close_room(&self, room_id: u64) {
         self.room_manager.remove_room(room_id).await;
         println!("关闭房间 {} 成功", room_id);
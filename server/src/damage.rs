@@ -0,0 +1,277 @@
+use common::sprites::{
+    Sprite,
+    attributes::{Attribute, SkillType},
+    skills::Skill,
+};
+use rand::Rng;
+
+use crate::game_rule::get_attribute_relationship;
+
+/// 暴击概率
+const CRITICAL_CHANCE: f64 = 1.0 / 16.0;
+/// 暴击伤害倍数
+const CRITICAL_MULTIPLIER: f32 = 1.5;
+/// 伤害浮动的下限(百分比), 最终伤害在该比例到100%之间浮动
+const MIN_VARIANCE: u8 = 85;
+/// 技能属性与攻击方精灵属性相同时的伤害倍数
+const SAME_ATTRIBUTE_BONUS: f32 = 1.5;
+
+/// 伤害计算结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DamageResult {
+    /// 属性克制倍数
+    pub multiplier: f32,
+    /// 是否暴击
+    pub is_critical: bool,
+    /// 最终造成的HP伤害
+    pub damage: u16,
+}
+
+/// 计算攻击方精灵使用技能对防守方精灵造成的伤害
+///
+/// # Arguments
+///
+/// * `attacker` - 攻击方精灵
+/// * `defender` - 防守方精灵
+/// * `skill` - 攻击方使用的技能
+/// * `rng` - 用于暴击判定与伤害浮动的随机数生成器
+pub fn calculate_damage(
+    attacker: &Sprite,
+    defender: &Sprite,
+    skill: &Skill,
+    rng: &mut impl Rng,
+) -> DamageResult {
+    let multiplier = attribute_multiplier(skill.attribute, defender);
+    let is_critical = rng.random_bool(CRITICAL_CHANCE);
+    let variance = rng.random_range(MIN_VARIANCE..=100) as f32 / 100.0;
+    compute_damage(attacker, defender, skill, multiplier, is_critical, variance)
+}
+
+/// 伤害公式
+///
+/// `((2 * 等级 / 5 + 2) * 威力 * 攻击 / 防御 / 50 + 2) * 同属性加成 * 属性克制倍数 * 暴击倍数 * 伤害浮动`
+///
+/// 物理技能使用 `phy_atk`/`phy_def`，法术技能使用 `mag_atk`/`mag_def`
+pub fn compute_damage(
+    attacker: &Sprite,
+    defender: &Sprite,
+    skill: &Skill,
+    multiplier: f32,
+    is_critical: bool,
+    variance: f32,
+) -> DamageResult {
+    let (attack, defense) = match skill.skill_type {
+        SkillType::Physical => (attacker.phy_atk, defender.phy_def),
+        SkillType::Magical => (attacker.mag_atk, defender.mag_def),
+    };
+    let level_factor = 2.0 * attacker.level as f32 / 5.0 + 2.0;
+    let base =
+        level_factor * skill.power as f32 * attack as f32 / defense.max(1) as f32 / 50.0 + 2.0;
+    let critical = if is_critical {
+        CRITICAL_MULTIPLIER
+    } else {
+        1.0
+    };
+    let same_attribute = if is_same_attribute(attacker, skill.attribute) {
+        SAME_ATTRIBUTE_BONUS
+    } else {
+        1.0
+    };
+    let damage = base * same_attribute * multiplier * critical * variance;
+
+    DamageResult {
+        multiplier,
+        is_critical,
+        // 属性免疫时不造成伤害，否则至少造成1点伤害
+        damage: if multiplier <= 0.0 {
+            0
+        } else {
+            damage.clamp(1.0, u16::MAX as f32) as u16
+        },
+    }
+}
+
+/// 查询属性克制关系时使用的防守方属性
+///
+/// 两个单属性能组成双属性时按双属性查询，否则分别查询每个属性；没有属性的精灵按无属性处理
+pub fn defender_attributes(defender: &Sprite) -> Vec<Attribute> {
+    match defender.attributes.as_slice() {
+        [] => vec![Attribute::None],
+        [first, second] => match Attribute::dual(*first, *second) {
+            Some(dual) => vec![dual],
+            None => vec![*first, *second],
+        },
+        attributes => attributes.to_vec(),
+    }
+}
+
+/// 技能属性对防守方精灵的克制倍数，不能组成双属性的两个属性的倍数相乘
+pub fn attribute_multiplier(attribute: Attribute, defender: &Sprite) -> f32 {
+    defender_attributes(defender)
+        .into_iter()
+        .map(|target_attribute| get_attribute_relationship(attribute, target_attribute))
+        .product()
+}
+
+/// 技能属性是否与攻击方精灵的属性(或其组成的双属性)相同，无属性的技能没有加成
+fn is_same_attribute(attacker: &Sprite, attribute: Attribute) -> bool {
+    if attribute == Attribute::None {
+        return false;
+    }
+    attacker.attributes.contains(&attribute)
+        || matches!(attacker.attributes.as_slice(), [first, second] if Attribute::dual(*first, *second) == Some(attribute))
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::fixtures;
+
+    fn sprite(level: u8) -> Sprite {
+        Sprite {
+            level,
+            phy_def: 150,
+            mag_atk: 150,
+            ..fixtures::sprite(1)
+        }
+    }
+
+    fn skill(skill_type: SkillType) -> Skill {
+        Skill {
+            id: 1,
+            name: "普通攻击".to_string(),
+            description: String::new(),
+            skill_type,
+            attribute: Attribute::Huo,
+            pp: 10,
+            max_pp: 10,
+            power: 100,
+            accuracy: 100,
+            priority: 0,
+            special_effect: None,
+        }
+    }
+
+    #[test]
+    fn test_physical_and_magical_damage() {
+        let attacker = sprite(100);
+        let defender = sprite(100);
+        // (42 * 100 * 300 / 150 / 50 + 2) = 170
+        let physical = compute_damage(
+            &attacker,
+            &defender,
+            &skill(SkillType::Physical),
+            1.0,
+            false,
+            1.0,
+        );
+        assert_eq!(physical.damage, 170);
+        // (42 * 100 * 150 / 300 / 50 + 2) = 44
+        let magical = compute_damage(
+            &attacker,
+            &defender,
+            &skill(SkillType::Magical),
+            1.0,
+            false,
+            1.0,
+        );
+        assert_eq!(magical.damage, 44);
+    }
+
+    #[test]
+    fn test_multiplier_and_critical() {
+        let attacker = sprite(100);
+        let defender = sprite(100);
+        let skill = skill(SkillType::Physical);
+
+        let result = compute_damage(&attacker, &defender, &skill, 2.0, true, 1.0);
+        assert_eq!(result.damage, 510);
+        assert!(result.is_critical);
+
+        let result = compute_damage(&attacker, &defender, &skill, 0.0, false, 1.0);
+        assert_eq!(result.damage, 0);
+    }
+
+    #[test]
+    fn test_level_scaling() {
+        let defender = sprite(100);
+        let skill = skill(SkillType::Physical);
+        let low = compute_damage(&sprite(10), &defender, &skill, 1.0, false, 1.0);
+        let high = compute_damage(&sprite(100), &defender, &skill, 1.0, false, 1.0);
+        assert!(low.damage < high.damage);
+    }
+
+    #[test]
+    fn test_calculate_damage_uses_attribute_relationship() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let result = calculate_damage(
+            &sprite(100),
+            &sprite(100),
+            &skill(SkillType::Physical),
+            &mut rng,
+        );
+        assert_eq!(result.multiplier, 1.0);
+    }
+
+    #[test]
+    fn test_same_attribute_bonus() {
+        let defender = sprite(100);
+        let skill = skill(SkillType::Physical);
+        let attacker = Sprite {
+            attributes: vec![Attribute::Huo],
+            ..sprite(100)
+        };
+        // 170 * 1.5 = 255
+        let result = compute_damage(&attacker, &defender, &skill, 1.0, false, 1.0);
+        assert_eq!(result.damage, 255);
+
+        // 木与灵组成木灵双属性, 木灵属性技能同样有加成
+        let attacker = Sprite {
+            attributes: vec![Attribute::Ling, Attribute::Mu],
+            ..sprite(100)
+        };
+        let skill = Skill {
+            attribute: Attribute::Wuling,
+            ..skill
+        };
+        let result = compute_damage(&attacker, &defender, &skill, 1.0, false, 1.0);
+        assert_eq!(result.damage, 255);
+    }
+
+    #[test]
+    fn test_defender_attributes() {
+        let defender = |attributes| Sprite {
+            attributes,
+            ..sprite(100)
+        };
+        assert_eq!(
+            defender_attributes(&defender(vec![])),
+            vec![Attribute::None]
+        );
+        assert_eq!(
+            defender_attributes(&defender(vec![Attribute::Ling, Attribute::Mu])),
+            vec![Attribute::Wuling]
+        );
+        // 凤不是风, 火与凤不组成火风
+        assert_eq!(
+            defender_attributes(&defender(vec![Attribute::Feng, Attribute::Huo])),
+            vec![Attribute::Feng, Attribute::Huo]
+        );
+        // 不能组成双属性时两个属性的克制倍数相乘
+        let dual = defender(vec![Attribute::JiXie, Attribute::Shui]);
+        assert_eq!(
+            attribute_multiplier(Attribute::Huo, &dual),
+            get_attribute_relationship(Attribute::Huo, Attribute::JiXie)
+                * get_attribute_relationship(Attribute::Huo, Attribute::Shui)
+        );
+        assert_eq!(
+            attribute_multiplier(
+                Attribute::Shui,
+                &defender(vec![Attribute::Mu, Attribute::Ling])
+            ),
+            get_attribute_relationship(Attribute::Shui, Attribute::Wuling)
+        );
+    }
+}
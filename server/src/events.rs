@@ -1,26 +1,34 @@
+use common::message::MatchMode;
 use tokio::sync::broadcast;
 
-use common::{message::ServerPayload, sprites::Sprite};
+use crate::room::BattleResult;
 
 #[derive(Debug, Clone)]
 pub enum ServerEvent {
-    /// 发送消息给指定玩家
-    SendMessageToPlayer {
+    PlayerReadyForMatchmaking {
         player_id: u64,
-        payload: ServerPayload,
+        mode: MatchMode,
     },
-    PlayerReadyForMatchmaking {
+    /// 玩家断开连接
+    PlayerDisconnected {
         player_id: u64,
     },
     /// 匹配成功，通知系统创建对战房间
     MatchFound {
         players: [u64; 2],
+        mode: MatchMode,
     },
     /// 房间创建成功
     RoomCreated {
         room_id: u64,
         players: [u64; 2],
     },
+    /// 战斗结束, 通知系统关闭房间
+    BattleFinished {
+        room_id: u64,
+        players: [u64; 2],
+        result: BattleResult,
+    },
     /// 关闭/销毁房间
     CloseRoom {
         room_id: u64,
@@ -38,6 +46,12 @@ pub struct EventBus {
     sender: broadcast::Sender<ServerEvent>,
 }
 
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EventBus {
     pub fn new() -> Self {
         Self {
@@ -0,0 +1,24 @@
+//! 测试中共用的精灵
+
+use common::sprites::Sprite;
+
+/// 满级、400HP、各项能力为300、速度为100的无属性精灵, 不携带技能
+///
+/// 测试需要不同的能力值时使用结构体更新语法覆盖对应的字段
+pub fn sprite(id: u64) -> Sprite {
+    Sprite {
+        id,
+        attributes: vec![],
+        level: 100,
+        exp: 0,
+        max_exp: 10000,
+        hp: 400,
+        max_hp: 400,
+        phy_atk: 300,
+        phy_def: 300,
+        mag_atk: 300,
+        mag_def: 300,
+        speed: 100,
+        skills: vec![],
+    }
+}
@@ -1,27 +1,147 @@
-use std::{collections::HashMap, sync::LazyLock};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::LazyLock,
+};
 
 use common::sprites::attributes::Attribute;
 
+use crate::config::{self, ConfigProblems};
+
+/// 属性克制表的配置文件
+const CONFIG_FILE: &str = "attribute_relationship.json";
+
 /// 正常属性之间的克制关系倍数
 const NORMAL: f32 = 1.0;
 
-static ATTRIBUTE_RELATIONSHIP: LazyLock<HashMap<Attribute, HashMap<Attribute, f32>>> =
+/// 属性克制关系表: 攻击方属性 -> (防守方属性 -> 克制倍数)
+pub type AttributeRelationship = HashMap<Attribute, HashMap<Attribute, f32>>;
+
+static ATTRIBUTE_RELATIONSHIP: LazyLock<Result<AttributeRelationship, ConfigProblems>> =
     LazyLock::new(|| {
         // 读取配置文件
         let config = include_str!("../configs/attribute_relationship.json");
-        // 解析配置文件
-        let config: HashMap<Attribute, HashMap<Attribute, f32>> =
-            serde_json::from_str(config).unwrap();
-        println!("{:?}", config);
-        config
+        // 解析并校验配置文件
+        parse_attribute_relationship(config)
     });
 
+/// 校验属性克制表覆盖了所有属性之间的克制倍数
+pub fn check_attribute_relationship() -> Result<(), ConfigProblems> {
+    config::check(&ATTRIBUTE_RELATIONSHIP)
+}
+
+/// 解析属性克制表, 检查:
+/// * 未知的攻击方属性或防守方属性
+/// * 非法的克制倍数(负数、NaN、无穷大)
+/// * 缺少某个攻击方属性的克制关系, 或某个攻击方属性下缺少防守方属性,
+///   配置需要覆盖 [`Attribute`] 所有变体之间的克制倍数
+pub fn parse_attribute_relationship(config: &str) -> Result<AttributeRelationship, ConfigProblems> {
+    let raw: BTreeMap<String, BTreeMap<String, f32>> = serde_json::from_str(config)
+        .map_err(|e| ConfigProblems::new(CONFIG_FILE, vec![format!("JSON 格式错误: {}", e)]))?;
+
+    let mut problems = Vec::new();
+    let mut relationship = AttributeRelationship::new();
+    for (attribute_name, targets) in &raw {
+        let Some(attribute) = parse_attribute(attribute_name) else {
+            problems.push(format!("未知的攻击方属性 \"{}\"", attribute_name));
+            continue;
+        };
+        let mut row = HashMap::new();
+        for (target_name, multiplier) in targets {
+            let Some(target_attribute) = parse_attribute(target_name) else {
+                problems.push(format!(
+                    "攻击方属性 {:?} 下未知的防守方属性 \"{}\"",
+                    attribute, target_name
+                ));
+                continue;
+            };
+            if !multiplier.is_finite() || *multiplier < 0.0 {
+                problems.push(format!(
+                    "{:?} -> {:?} 的克制倍数 {} 不合法",
+                    attribute, target_attribute, multiplier
+                ));
+                continue;
+            }
+            row.insert(target_attribute, *multiplier);
+        }
+        for target_attribute in Attribute::ALL {
+            if !targets.contains_key(&format!("{:?}", target_attribute)) {
+                problems.push(format!(
+                    "攻击方属性 {:?} 缺少防守方属性 {:?} 的克制倍数",
+                    attribute, target_attribute
+                ));
+            }
+        }
+        relationship.insert(attribute, row);
+    }
+
+    for attribute in Attribute::ALL {
+        if !relationship.contains_key(&attribute) {
+            problems.push(format!("缺少攻击方属性 {:?} 的克制关系", attribute));
+        }
+    }
+
+    ConfigProblems::check(CONFIG_FILE, relationship, problems)
+}
+
 /// 获取属性之间的关系
 pub fn get_attribute_relationship(attribute: Attribute, target_attribute: Attribute) -> f32 {
-    ATTRIBUTE_RELATIONSHIP
+    config::get(&ATTRIBUTE_RELATIONSHIP)
         .get(&attribute)
-        .unwrap_or_else(|| panic!("Attribute {:?} not found", attribute))
-        .get(&target_attribute)
-        .unwrap_or(&NORMAL)
-        .clone()
+        .and_then(|targets| targets.get(&target_attribute))
+        .copied()
+        .unwrap_or(NORMAL)
+}
+
+/// 按配置中的名称解析属性, 名称与 [`Attribute`] 的变体名一致
+fn parse_attribute(name: &str) -> Option<Attribute> {
+    serde_json::from_value(serde_json::Value::String(name.to_owned())).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_attribute_relationship() {
+        assert_eq!(
+            get_attribute_relationship(Attribute::Huo, Attribute::JiXie),
+            2.0
+        );
+        assert_eq!(
+            get_attribute_relationship(Attribute::Special, Attribute::Huo),
+            NORMAL
+        );
+    }
+
+    #[test]
+    fn test_parse_attribute_relationship() {
+        let config = r#"{
+            "Huo": { "Jixie": 2.0, "Shui": -1.0 },
+            "HuoFeng": {}
+        }"#;
+        let error = parse_attribute_relationship(config).unwrap_err();
+        assert!(
+            error
+                .problems
+                .contains(&"未知的攻击方属性 \"HuoFeng\"".to_string())
+        );
+        assert!(
+            error
+                .problems
+                .contains(&"攻击方属性 Huo 下未知的防守方属性 \"Jixie\"".to_string())
+        );
+        assert!(error.problems.iter().any(|p| p.contains("Shui")));
+        assert!(
+            error
+                .problems
+                .contains(&"攻击方属性 Huo 缺少防守方属性 Mu 的克制倍数".to_string())
+        );
+        // 除了 Huo 以外的属性都缺少克制关系
+        let missing = error
+            .problems
+            .iter()
+            .filter(|p| p.starts_with("缺少攻击方属性"))
+            .count();
+        assert_eq!(missing, Attribute::ALL.len() - 1);
+    }
 }
@@ -0,0 +1,20 @@
+pub mod account;
+pub mod actor;
+pub mod battle_rules;
+pub mod catalog;
+pub mod config;
+pub mod coordinator;
+pub mod damage;
+pub mod events;
+#[cfg(test)]
+mod fixtures;
+pub mod game_rule;
+pub mod matchmaking;
+pub mod replay;
+pub mod room;
+pub mod room_manager;
+pub mod session;
+pub mod simulator;
+pub mod stat_stage;
+pub mod status;
+pub mod status_effect;
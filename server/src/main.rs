@@ -1,49 +1,75 @@
-mod actor;
-mod coordinator;
-mod events;
-mod game_rule;
-mod matchmaking;
-mod room;
-mod room_manager;
-mod session;
-mod status;
-use actor::{ActorMessage, PlayerActor};
 use common::{
     message::{ClientMessage, ClientPayload, GameMessageCodec, ServerMessage, ServerPayload},
-    security::genenrate_token,
+    security::{TokenConfig, genenrate_token, issue_refresh_token, validate_refresh_token},
 };
-use events::EventBus;
-use events::ServerEvent;
 use futures_util::{
     SinkExt, StreamExt,
     stream::{SplitSink, SplitStream},
 };
-use std::{
-    net::SocketAddr,
-    sync::atomic::{AtomicU64, Ordering},
-    time::Duration,
+use server::{
+    account::{AccountService, AccountStore, FileAccountStore, MemoryAccountStore},
+    actor::{ActorMessage, PlayerActor},
+    battle_rules, catalog, config,
+    coordinator::GameCoordinator,
+    events::EventBus,
+    game_rule,
+    matchmaking::MatchmakingService,
+    room_manager::RoomManager,
+    session::{OUTBOUND_CAPACITY, Outbound, SessionManager},
 };
-
-use session::SessionManager;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use tokio::{
     net::{TcpListener, TcpStream},
-    sync::{broadcast, mpsc},
+    sync::mpsc,
     time::sleep,
 };
 use tokio_util::codec::Framed;
 
-use crate::{
-    coordinator::GameCoordinator, matchmaking::MatchmakingService, room_manager::RoomManager,
-};
-
-/// player_id: 使用自增Key 或者 雪花算法[`snowflake`](https://github.com/BinChengZhao/snowflake-rs)生成的唯一ID
-static PLAYER_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
 #[tokio::main]
 async fn main() {
+    // 启动前读取并校验配置, 配置有误时直接退出
+    println!("配置目录: {}", config::config_dir().display());
+    if let Err(e) = game_rule::check_attribute_relationship() {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = battle_rules::check_battle_rules() {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = catalog::check_catalog() {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+
+    // 设置了 ACCOUNTS_FILE 时账号保存到文件, 否则只保存在内存中
+    let account_store: Arc<dyn AccountStore> = match std::env::var("ACCOUNTS_FILE") {
+        Ok(path) => match FileAccountStore::open(&path) {
+            Ok(store) => Arc::new(store),
+            Err(e) => {
+                println!("打开账号文件 {} 失败: {:?}", path, e);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => {
+            println!("未设置 ACCOUNTS_FILE, 账号只保存在内存中");
+            Arc::new(MemoryAccountStore::new())
+        }
+    };
+    let account_service = AccountService::new(account_store);
+    let token_config = Arc::new(load_token_config());
+
     let listener = TcpListener::bind("127.0.0.1:5555").await.unwrap();
     let session_manager = SessionManager::new();
-    let room_manager = RoomManager::new();
+    // 设置了 REPLAY_DIR 时战斗结束后保存回放
+    let room_manager = match std::env::var("REPLAY_DIR") {
+        Ok(replay_dir) => RoomManager::new().with_replay_dir(replay_dir),
+        Err(_) => {
+            println!("未设置 REPLAY_DIR, 不保存战斗回放");
+            RoomManager::new()
+        }
+    };
     let event_bus = EventBus::new();
 
     let mut matchmaking_service = MatchmakingService::new(event_bus.clone());
@@ -69,13 +95,25 @@ async fn main() {
         let session_manager = session_manager.clone();
         let room_manager = room_manager.clone();
         let event_bus = event_bus.clone();
+        let account_service = account_service.clone();
+        let token_config = token_config.clone();
         tokio::spawn(async move {
             // 使用解码器包装 socket
             let framed = Framed::new(
                 socket,
                 GameMessageCodec::<ServerMessage, ClientMessage>::default(),
             );
-            if let Err(e) = process(framed, addr, session_manager, room_manager, event_bus).await {
+            if let Err(e) = process(
+                framed,
+                addr,
+                session_manager,
+                room_manager,
+                event_bus,
+                account_service,
+                token_config,
+            )
+            .await
+            {
                 println!("处理连接 {} 时出错: {:?}", addr, e)
             };
         });
@@ -88,27 +126,35 @@ async fn process(
     session_manager: SessionManager,
     room_manager: RoomManager,
     event_bus: EventBus,
+    account_service: AccountService,
+    token_config: Arc<TokenConfig>,
 ) -> anyhow::Result<()> {
     println!("接收到来自: {}的连接", addr);
-    // 订阅事件
-    let mut event_subscriber = event_bus.subscribe();
 
     let (mut sink, mut stream) = framed.split();
 
-    // 处理注册登录TODO:
-    // let player_id = authenticate(&mut sink, &mut stream).await?;
-    let player_id = PLAYER_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+    // 处理注册登录
+    let player_id = authenticate(
+        &mut sink,
+        &mut stream,
+        &account_service,
+        &session_manager,
+        &token_config,
+    )
+    .await?;
 
     let (actor_sender, actor_receiver) = mpsc::channel(128);
+    let (outbound_sender, mut outbound_receiver) = mpsc::channel(OUTBOUND_CAPACITY);
     session_manager
-        .register(player_id, actor_sender.clone())
+        .register(player_id, actor_sender.clone(), outbound_sender)
         .await;
     let mut actor = PlayerActor::new(
         player_id,
         actor_receiver,
         event_bus,
-        session_manager,
+        session_manager.clone(),
         room_manager,
+        token_config,
     );
 
     // 启动Actor
@@ -137,59 +183,69 @@ async fn process(
                     }
                 }
             },
-            event = event_subscriber.recv() =>{
-                match event {
-                    Ok(server_event) => {
-                        handle_server_event(player_id, server_event, &mut sink).await?;
-                    },
-                    Err(broadcast::error::RecvError::Lagged(n)) => {
-                        // 如果处理速度太慢，导致消息丢失
-                        println!("用户:{} 接收事件失败: 丢失 {} 个事件", player_id, n);
+            outbound = outbound_receiver.recv() => {
+                match outbound {
+                    Some(Outbound::Payload(payload)) => {
+                        sink.send(ServerMessage { sequence: 0, payload }).await?;
+                    }
+                    Some(Outbound::Disconnect) => {
+                        println!("[process] 服务端主动断开玩家 {} 的连接", player_id);
+                        break;
                     }
-                    Err(_) => break,
+                    // 会话已被注销
+                    None => break,
                 }
             }
         }
     }
 
     // -- 断开连接 --
+    session_manager.unregister(player_id, &actor_sender).await;
     actor_sender.send(ActorMessage::Disconnect).await?;
     println!("[process] 玩家 {} 断开连接", player_id);
     Ok(())
 }
 
-async fn handle_server_event(
-    player_id: u64,
-    server_event: ServerEvent,
-    sink: &mut SplitSink<
-        Framed<TcpStream, GameMessageCodec<ServerMessage, ClientMessage>>,
-        ServerMessage,
-    >,
-) -> anyhow::Result<()> {
-    match server_event {
-        ServerEvent::SendMessageToPlayer {
-            player_id: target_player_id,
-            payload,
-        } => {
-            if player_id == target_player_id {
-                let msg = ServerMessage {
-                    sequence: 0,
-                    payload,
-                };
-                sink.send(msg).await?;
+/// 加载 token 配置
+///
+/// 配置文件路径由环境变量 `TOKEN_CONFIG` 指定, 格式参考 `configs/token.example.json`。
+/// 未设置时使用进程内随机生成的临时密钥, 服务器重启后所有 token 失效
+fn load_token_config() -> TokenConfig {
+    match std::env::var("TOKEN_CONFIG") {
+        Ok(path) => match TokenConfig::from_file(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
             }
+        },
+        Err(_) => {
+            println!("未设置 TOKEN_CONFIG, 使用临时生成的签名密钥");
+            TokenConfig::new(
+                jsonwebtoken::Algorithm::HS256,
+                "game_server",
+                "game_client",
+                chrono::Duration::hours(1),
+                "ephemeral",
+                [("ephemeral".to_owned(), rand::random::<[u8; 32]>().to_vec())],
+            )
+            .expect("临时 token 配置")
         }
-        _ => {}
     }
-    Ok(())
 }
 
+type ServerSink =
+    SplitSink<Framed<TcpStream, GameMessageCodec<ServerMessage, ClientMessage>>, ServerMessage>;
+
+/// 处理注册登录, 登录成功后返回玩家ID
+///
+/// 除了用户名密码, 重新连接的客户端也可以使用 refresh token 登录
 async fn authenticate(
-    sink: &mut SplitSink<
-        Framed<TcpStream, GameMessageCodec<ServerMessage, ClientMessage>>,
-        ServerMessage,
-    >,
+    sink: &mut ServerSink,
     stream: &mut SplitStream<Framed<TcpStream, GameMessageCodec<ServerMessage, ClientMessage>>>,
+    account_service: &AccountService,
+    session_manager: &SessionManager,
+    token_config: &TokenConfig,
 ) -> anyhow::Result<u64> {
     let auth_timeout = sleep(Duration::from_secs(10));
     tokio::pin!(auth_timeout);
@@ -200,27 +256,74 @@ async fn authenticate(
                 match frame {
                     Some(Ok(frame)) => {
                         match frame.payload {
+                            ClientPayload::Register { username, password } => {
+                                let payload = match account_service.register(username, password).await {
+                                    Ok(player_id) => {
+                                        println!("玩家 {} 注册成功", player_id);
+                                        ServerPayload::RegisterSuccess(player_id)
+                                    }
+                                    Err(e) => {
+                                        println!("注册失败: {}", e);
+                                        ServerPayload::RegisterFailed(e.to_string())
+                                    }
+                                };
+                                sink.send(ServerMessage { sequence: 0, payload }).await?;
+                            }
                             ClientPayload::Login{
                                 username,
                                 password,
                             } => {
-                                // 验证用户名密码TOOD: 需要从数据库验证
-                                if username == "account" && password =="password" {
-                                    let player_id = 1;
-                                    let token = genenrate_token(player_id);
-                                    let response = ServerMessage {
-                                        sequence: 0,
-                                        payload:ServerPayload::LoginSuccess(token),
-                                    };
-                                    sink.send(response).await?;
+                                match account_service.login(username, password).await {
+                                    Ok(player_id) => {
+                                        send_login_success(
+                                            sink,
+                                            session_manager,
+                                            token_config,
+                                            player_id,
+                                            None,
+                                        )
+                                        .await?;
+                                        return Ok(player_id);
+                                    }
+                                    Err(e) => {
+                                        println!("登录失败: {}", e);
+                                        let response = ServerMessage {
+                                            sequence: 0,
+                                            payload: ServerPayload::LoginFailed,
+                                        };
+                                        sink.send(response).await?;
+                                    }
+                                }
+                            }
+                            ClientPayload::RefreshToken { refresh_token } => {
+                                let logged_in = match validate_refresh_token(token_config, &refresh_token) {
+                                    Ok(claims) => {
+                                        let player_id = claims.subject();
+                                        send_login_success(
+                                            sink,
+                                            session_manager,
+                                            token_config,
+                                            player_id,
+                                            Some(claims.id()),
+                                        )
+                                        .await?
+                                        .then_some(player_id)
+                                    }
+                                    Err(e) => {
+                                        println!("校验 refresh token 失败: {:?}", e);
+                                        None
+                                    }
+                                };
+                                if let Some(player_id) = logged_in {
+                                    println!("玩家 {} 使用 refresh token 重新登录", player_id);
                                     return Ok(player_id);
-                                } else {
-                                    let response = ServerMessage {
-                                        sequence: 0,
-                                        payload: ServerPayload::LoginFailed,
-                                    };
-                                    sink.send(response).await?;
                                 }
+                                println!("refresh token 无效或已被轮换, 登录失败");
+                                let response = ServerMessage {
+                                    sequence: 0,
+                                    payload: ServerPayload::LoginFailed,
+                                };
+                                sink.send(response).await?;
                             }
                             _ => {
                                 let response = ServerMessage {
@@ -228,7 +331,7 @@ async fn authenticate(
                                     payload: ServerPayload::LoginFailed,
                                 };
                                 sink.send(response).await?;
-                                println!("认证失败，期望 Login 消息");
+                                println!("认证失败，期望 Register、Login 或 RefreshToken 消息");
                             }
                         }
                     }
@@ -250,9 +353,43 @@ async fn authenticate(
     }
 }
 
+/// 签发 token 与新的 refresh token 并通知客户端登录成功, 返回是否登录成功
+///
+/// 使用 refresh token 登录时, 只接受该玩家当前有效的 refresh token, 并将其轮换
+async fn send_login_success(
+    sink: &mut ServerSink,
+    session_manager: &SessionManager,
+    token_config: &TokenConfig,
+    player_id: u64,
+    used_refresh_token_id: Option<&str>,
+) -> anyhow::Result<bool> {
+    let (refresh_token, refresh_claims) = issue_refresh_token(token_config, player_id);
+    if !session_manager
+        .rotate_refresh_token(
+            player_id,
+            used_refresh_token_id,
+            refresh_claims.id().to_owned(),
+        )
+        .await
+    {
+        return Ok(false);
+    }
+    let response = ServerMessage {
+        sequence: 0,
+        payload: ServerPayload::LoginSuccess {
+            token: genenrate_token(token_config, player_id),
+            refresh_token,
+        },
+    };
+    sink.send(response).await?;
+    Ok(true)
+}
+
 #[cfg(test)]
 mod test {
 
+    use common::message::{BattleEndReason, ClientAction, MatchMode, RoomAction, RoomActionError};
+    use server::{actor::SystemMessage, events::ServerEvent, room::BattleResult};
     use tokio::time::Instant;
 
     use super::*;
@@ -261,50 +398,41 @@ mod test {
     async fn test_battle() {
         let (event_bus, room_manager, session_manager) = start_server();
         let event_bus_b = event_bus.clone();
+        let token_config = Arc::new(load_token_config());
 
         let player_id = 1;
 
-        let (actor_sender, actor_receiver) = mpsc::channel(128);
-        let mut actor = PlayerActor::new(
+        let _player = spawn_player(
             player_id,
-            actor_receiver,
-            event_bus.clone(),
-            session_manager.clone(),
-            room_manager.clone(),
-        );
-        session_manager
-            .register(player_id, actor_sender.clone())
-            .await;
-
-        // 启动Actor
-        tokio::spawn(async move {
-            actor.run().await.unwrap();
-        });
+            &event_bus,
+            &room_manager,
+            &session_manager,
+            &token_config,
+        )
+        .await;
 
         let player_id = 2;
 
-        let (actor_sender, actor_receiver) = mpsc::channel(128);
-        let mut actor = PlayerActor::new(
+        let _player = spawn_player(
             player_id,
-            actor_receiver,
-            event_bus.clone(),
-            session_manager.clone(),
-            room_manager.clone(),
-        );
-        session_manager
-            .register(player_id, actor_sender.clone())
-            .await;
-
-        // 启动Actor
-        tokio::spawn(async move {
-            actor.run().await.unwrap();
-        });
+            &event_bus,
+            &room_manager,
+            &session_manager,
+            &token_config,
+        )
+        .await;
 
         let a = tokio::spawn(async move {
-            event_bus.publish(ServerEvent::PlayerReadyForMatchmaking { player_id: 1 });
+            event_bus.publish(ServerEvent::PlayerReadyForMatchmaking {
+                player_id: 1,
+                mode: MatchMode::Casual,
+            });
         });
         let b = tokio::spawn(async move {
-            event_bus_b.publish(ServerEvent::PlayerReadyForMatchmaking { player_id: 2 });
+            event_bus_b.publish(ServerEvent::PlayerReadyForMatchmaking {
+                player_id: 2,
+                mode: MatchMode::Casual,
+            });
         });
         assert!(a.await.is_ok());
         assert!(b.await.is_ok());
@@ -329,7 +457,10 @@ mod test {
             event_bus_clones.push(event_bus_clone.clone());
 
             tasks.push(tokio::spawn(async move {
-                event_bus_clone.publish(ServerEvent::PlayerReadyForMatchmaking { player_id: i });
+                event_bus_clone.publish(ServerEvent::PlayerReadyForMatchmaking {
+                    player_id: i,
+                    mode: MatchMode::Casual,
+                });
             }));
         }
 
@@ -378,14 +509,20 @@ mod test {
 
     #[tokio::test]
     async fn test_create_room() {
-        let (event_bus, room_manager, session_manager) = start_server();
+        let (event_bus, room_manager, _) = start_server();
         let event_bus_b = event_bus.clone();
         let event_bus_c = event_bus.clone();
         let a = tokio::spawn(async move {
-            event_bus.publish(ServerEvent::PlayerReadyForMatchmaking { player_id: 1 });
+            event_bus.publish(ServerEvent::PlayerReadyForMatchmaking {
+                player_id: 1,
+                mode: MatchMode::Casual,
+            });
         });
         let b = tokio::spawn(async move {
-            event_bus_b.publish(ServerEvent::PlayerReadyForMatchmaking { player_id: 2 });
+            event_bus_b.publish(ServerEvent::PlayerReadyForMatchmaking {
+                player_id: 2,
+                mode: MatchMode::Casual,
+            });
         });
         assert!(a.await.is_ok());
         assert!(b.await.is_ok());
@@ -407,6 +544,393 @@ mod test {
         assert_eq!(room_count, 0);
     }
 
+    #[tokio::test]
+    async fn test_close_room_when_battle_finished() {
+        let (event_bus, room_manager, session_manager) = start_server();
+        // 等待 GameCoordinator 启动并订阅事件
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let room_id = room_manager
+            .create_room(
+                [1, 2],
+                MatchMode::Casual,
+                event_bus.clone(),
+                session_manager,
+            )
+            .await;
+        assert_eq!(room_manager.room_count().await, 1);
+
+        event_bus.publish(ServerEvent::BattleFinished {
+            room_id,
+            players: [1, 2],
+            result: BattleResult {
+                winner: Some(1),
+                reason: BattleEndReason::AllFainted,
+                turns: 3,
+            },
+        });
+        let start_time = Instant::now();
+        while room_manager.room_count().await != 0 && start_time.elapsed() < Duration::from_secs(2)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(room_manager.room_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_release_player_when_battle_finished() {
+        let (event_bus, room_manager, session_manager) = start_server();
+        let token_config = Arc::new(load_token_config());
+        // 等待 GameCoordinator 启动并订阅事件
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let player_id = 1;
+        let (actor_sender, mut outbound_receiver) = spawn_player(
+            player_id,
+            &event_bus,
+            &room_manager,
+            &session_manager,
+            &token_config,
+        )
+        .await;
+        let room_id = room_manager
+            .create_room(
+                [1, 2],
+                MatchMode::Casual,
+                event_bus.clone(),
+                session_manager.clone(),
+            )
+            .await;
+        actor_sender
+            .send(ActorMessage::SystemNotification(SystemMessage::EnterRoom {
+                room_id,
+                opponent_name: "2".to_string(),
+            }))
+            .await
+            .unwrap();
+
+        event_bus.publish(ServerEvent::BattleFinished {
+            room_id,
+            players: [1, 2],
+            result: BattleResult {
+                winner: Some(1),
+                reason: BattleEndReason::Escape,
+                turns: 1,
+            },
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // 战斗结束后玩家已离开房间, 房间操作被拒绝
+        actor_sender
+            .send(ActorMessage::ClientMessage(ClientMessage {
+                sequence: 1,
+                payload: ClientPayload::Authenticated {
+                    token: genenrate_token(&token_config, player_id),
+                    action: ClientAction::RoomAction(RoomAction::Escape),
+                },
+            }))
+            .await
+            .unwrap();
+        let outbound = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                match outbound_receiver.recv().await {
+                    Some(Outbound::Payload(payload @ ServerPayload::RoomActionRejected { .. })) => {
+                        break payload;
+                    }
+                    Some(_) => continue,
+                    None => panic!("连接已关闭"),
+                }
+            }
+        })
+        .await
+        .expect("应该收到拒绝消息");
+        assert!(matches!(
+            outbound,
+            ServerPayload::RoomActionRejected {
+                action: RoomAction::Escape,
+                reason: RoomActionError::NotInRoom,
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_matchmaking_by_mode() {
+        let (event_bus, room_manager, session_manager) = start_server();
+        let token_config = Arc::new(load_token_config());
+        // 等待 MatchmakingService 启动并订阅事件
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut players = vec![];
+        for (player_id, mode) in [
+            (1, MatchMode::Ranked),
+            (2, MatchMode::Casual),
+            (3, MatchMode::Ranked),
+        ] {
+            let (actor_sender, outbound_receiver) = spawn_player(
+                player_id,
+                &event_bus,
+                &room_manager,
+                &session_manager,
+                &token_config,
+            )
+            .await;
+            actor_sender
+                .send(ActorMessage::ClientMessage(ClientMessage {
+                    sequence: 1,
+                    payload: ClientPayload::Authenticated {
+                        token: genenrate_token(&token_config, player_id),
+                        action: ClientAction::StartMatchmaking { mode },
+                    },
+                }))
+                .await
+                .unwrap();
+            players.push((actor_sender, outbound_receiver));
+        }
+
+        // 只有两个排位赛玩家匹配成功
+        let start_time = Instant::now();
+        while room_manager.room_count().await == 0 && start_time.elapsed() < Duration::from_secs(2)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(room_manager.room_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reject_token_of_other_player() {
+        let (event_bus, room_manager, session_manager) = start_server();
+        let token_config = Arc::new(load_token_config());
+
+        let player_id = 1;
+        let (actor_sender, mut outbound_receiver) = spawn_player(
+            player_id,
+            &event_bus,
+            &room_manager,
+            &session_manager,
+            &token_config,
+        )
+        .await;
+
+        // 使用玩家2的 token 发送请求
+        let token = genenrate_token(&token_config, 2);
+        for sequence in 1..=3 {
+            actor_sender
+                .send(ActorMessage::ClientMessage(ClientMessage {
+                    sequence,
+                    payload: ClientPayload::Authenticated {
+                        token: token.clone(),
+                        action: ClientAction::SpriteTeam,
+                    },
+                }))
+                .await
+                .unwrap();
+        }
+
+        let mut auth_failed = 0;
+        loop {
+            let outbound = tokio::time::timeout(Duration::from_secs(1), outbound_receiver.recv())
+                .await
+                .expect("应该断开连接")
+                .unwrap();
+            match outbound {
+                Outbound::Payload(ServerPayload::AuthFailed) => auth_failed += 1,
+                Outbound::Payload(payload) => {
+                    panic!("不应该处理冒充的请求: {:?}", payload)
+                }
+                Outbound::Disconnect => break,
+            }
+        }
+        assert_eq!(auth_failed, 3);
+    }
+
+    #[tokio::test]
+    async fn test_reject_rotated_refresh_token() {
+        let (event_bus, room_manager, session_manager) = start_server();
+        let token_config = Arc::new(load_token_config());
+
+        // 模拟登录: 签发 refresh token 并记录为当前有效的 refresh token
+        let player_id = 1;
+        let (refresh_token, refresh_claims) = issue_refresh_token(&token_config, player_id);
+        session_manager
+            .rotate_refresh_token(player_id, None, refresh_claims.id().to_owned())
+            .await;
+        let (actor_sender, mut outbound_receiver) = spawn_player(
+            player_id,
+            &event_bus,
+            &room_manager,
+            &session_manager,
+            &token_config,
+        )
+        .await;
+
+        let mut refresh = async |sequence, refresh_token| {
+            actor_sender
+                .send(ActorMessage::ClientMessage(ClientMessage {
+                    sequence,
+                    payload: ClientPayload::RefreshToken { refresh_token },
+                }))
+                .await
+                .unwrap();
+            match tokio::time::timeout(Duration::from_secs(1), outbound_receiver.recv())
+                .await
+                .expect("应该收到刷新结果")
+                .unwrap()
+            {
+                Outbound::Payload(payload) => payload,
+                Outbound::Disconnect => panic!("不应该断开连接"),
+            }
+        };
+
+        let ServerPayload::TokenRefreshed {
+            refresh_token: rotated,
+            ..
+        } = refresh(1, refresh_token.clone()).await
+        else {
+            panic!("应该签发新的 token");
+        };
+        // 旧的 refresh token 在轮换后失效
+        assert!(matches!(
+            refresh(2, refresh_token).await,
+            ServerPayload::AuthFailed
+        ));
+        assert!(matches!(
+            refresh(3, rotated).await,
+            ServerPayload::TokenRefreshed { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reject_room_action_without_room() {
+        let (event_bus, room_manager, session_manager) = start_server();
+        let token_config = Arc::new(load_token_config());
+
+        let player_id = 1;
+        let (actor_sender, mut outbound_receiver) = spawn_player(
+            player_id,
+            &event_bus,
+            &room_manager,
+            &session_manager,
+            &token_config,
+        )
+        .await;
+
+        // 玩家不在任何房间中时提交逃跑操作
+        actor_sender
+            .send(ActorMessage::ClientMessage(ClientMessage {
+                sequence: 1,
+                payload: ClientPayload::Authenticated {
+                    token: genenrate_token(&token_config, player_id),
+                    action: ClientAction::RoomAction(RoomAction::Escape),
+                },
+            }))
+            .await
+            .unwrap();
+
+        let outbound = tokio::time::timeout(Duration::from_secs(1), outbound_receiver.recv())
+            .await
+            .expect("应该收到拒绝消息")
+            .unwrap();
+        assert!(matches!(
+            outbound,
+            Outbound::Payload(ServerPayload::RoomActionRejected {
+                action: RoomAction::Escape,
+                reason: RoomActionError::NotInRoom,
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_with_refresh_token() {
+        let (event_bus, room_manager, session_manager) = start_server();
+        let token_config = Arc::new(load_token_config());
+        let account_service = AccountService::new(Arc::new(MemoryAccountStore::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (socket, addr) = listener.accept().await.unwrap();
+                let framed = Framed::new(socket, GameMessageCodec::default());
+                tokio::spawn(process(
+                    framed,
+                    addr,
+                    session_manager.clone(),
+                    room_manager.clone(),
+                    event_bus.clone(),
+                    account_service.clone(),
+                    token_config.clone(),
+                ));
+            }
+        });
+
+        type Client = Framed<TcpStream, GameMessageCodec<ClientMessage, ServerMessage>>;
+        async fn connect(addr: SocketAddr) -> Client {
+            Framed::new(
+                TcpStream::connect(addr).await.unwrap(),
+                GameMessageCodec::default(),
+            )
+        }
+        async fn request(client: &mut Client, payload: ClientPayload) -> ServerPayload {
+            client
+                .send(ClientMessage {
+                    sequence: 0,
+                    payload,
+                })
+                .await
+                .unwrap();
+            tokio::time::timeout(Duration::from_secs(1), client.next())
+                .await
+                .expect("应该收到响应")
+                .unwrap()
+                .unwrap()
+                .payload
+        }
+        let refresh = |refresh_token| ClientPayload::RefreshToken { refresh_token };
+
+        let mut client = connect(server_addr).await;
+        let register = ClientPayload::Register {
+            username: "player_a".to_owned(),
+            password: "password".to_owned(),
+        };
+        assert!(matches!(
+            request(&mut client, register).await,
+            ServerPayload::RegisterSuccess(_)
+        ));
+        let ServerPayload::LoginSuccess { refresh_token, .. } = request(
+            &mut client,
+            ClientPayload::Login {
+                username: "player_a".to_owned(),
+                password: "password".to_owned(),
+            },
+        )
+        .await
+        else {
+            panic!("应该登录成功");
+        };
+        drop(client);
+
+        // 重新连接时使用 refresh token 登录, 同时轮换 refresh token
+        let mut client = connect(server_addr).await;
+        let ServerPayload::LoginSuccess {
+            refresh_token: rotated,
+            ..
+        } = request(&mut client, refresh(refresh_token.clone())).await
+        else {
+            panic!("应该使用 refresh token 登录成功");
+        };
+        drop(client);
+
+        // 轮换后旧的 refresh token 失效
+        let mut client = connect(server_addr).await;
+        assert!(matches!(
+            request(&mut client, refresh(refresh_token)).await,
+            ServerPayload::LoginFailed
+        ));
+        assert!(matches!(
+            request(&mut client, refresh(rotated)).await,
+            ServerPayload::LoginSuccess { .. }
+        ));
+    }
+
     fn start_server() -> (EventBus, RoomManager, SessionManager) {
         let event_bus = EventBus::new();
         let room_manager = RoomManager::new();
@@ -424,4 +948,33 @@ mod test {
 
         (event_bus, room_manager, session_manager)
     }
+
+    /// 启动玩家 Actor 并注册会话, 返回 Actor 的信箱与连接的发件箱
+    async fn spawn_player(
+        player_id: u64,
+        event_bus: &EventBus,
+        room_manager: &RoomManager,
+        session_manager: &SessionManager,
+        token_config: &Arc<TokenConfig>,
+    ) -> (mpsc::Sender<ActorMessage>, mpsc::Receiver<Outbound>) {
+        let (actor_sender, actor_receiver) = mpsc::channel(128);
+        let (outbound_sender, outbound_receiver) = mpsc::channel(OUTBOUND_CAPACITY);
+        let mut actor = PlayerActor::new(
+            player_id,
+            actor_receiver,
+            event_bus.clone(),
+            session_manager.clone(),
+            room_manager.clone(),
+            token_config.clone(),
+        );
+        session_manager
+            .register(player_id, actor_sender.clone(), outbound_sender)
+            .await;
+
+        // 启动Actor
+        tokio::spawn(async move {
+            actor.run().await.unwrap();
+        });
+        (actor_sender, outbound_receiver)
+    }
 }
@@ -1,41 +1,118 @@
+use std::collections::HashMap;
+
+use common::message::MatchMode;
+
 use crate::events::{EventBus, ServerEvent};
 
 pub struct MatchmakingService {
     event_bus: EventBus,
-    /// 匹配队列
-    queue: Vec<u64>,
+    /// 匹配队列, 只有相同对战模式的玩家才会匹配到一起, 一个玩家同时只在一个队列中
+    queues: HashMap<MatchMode, Vec<u64>>,
 }
 
 impl MatchmakingService {
     pub fn new(event_bus: EventBus) -> Self {
         Self {
             event_bus,
-            queue: Vec::new(),
+            queues: HashMap::new(),
         }
     }
 
     pub async fn run(&mut self) {
         let mut event_receiver = self.event_bus.subscribe();
         loop {
-            if let Ok(ServerEvent::PlayerReadyForMatchmaking { player_id }) =
-                event_receiver.recv().await
-            {
-                self.queue.push(player_id);
-                self.try_create_match();
+            match event_receiver.recv().await {
+                Ok(ServerEvent::PlayerReadyForMatchmaking { player_id, mode }) => {
+                    // 切换对战模式时离开之前的队列
+                    for (queue_mode, queue) in &mut self.queues {
+                        if *queue_mode != mode {
+                            queue.retain(|id| *id != player_id);
+                        }
+                    }
+                    let queue = self.queues.entry(mode).or_default();
+                    // 重复提交匹配请求时不重复入队
+                    if !queue.contains(&player_id) {
+                        queue.push(player_id);
+                    }
+                    self.try_create_match(mode);
+                }
+                Ok(ServerEvent::PlayerDisconnected { player_id }) => {
+                    self.leave_queues(player_id);
+                }
+                _ => {}
             }
         }
     }
 
-    fn try_create_match(&mut self) {
-        if self.queue.len() >= 2 {
-            let player_a = self.queue.remove(0);
-            let player_b = self.queue.remove(0);
+    /// 将玩家移出所有匹配队列
+    fn leave_queues(&mut self, player_id: u64) {
+        for queue in self.queues.values_mut() {
+            queue.retain(|id| *id != player_id);
+        }
+    }
+
+    fn try_create_match(&mut self, mode: MatchMode) {
+        let Some(queue) = self.queues.get_mut(&mode) else {
+            return;
+        };
+        if queue.len() >= 2 {
+            let player_a = queue.remove(0);
+            let player_b = queue.remove(0);
+            self.leave_queues(player_a);
+            self.leave_queues(player_b);
 
-            println!("[Matchmaking] Match found: {} vs {}", player_a, player_b);
+            println!(
+                "[Matchmaking] Match found ({:?}): {} vs {}",
+                mode, player_a, player_b
+            );
             // 匹配成功，通知系统创建对战房间
             self.event_bus.publish(ServerEvent::MatchFound {
                 players: [player_a, player_b],
+                mode,
             });
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_player_in_one_queue_only() {
+        let event_bus = EventBus::new();
+        let mut matchmaking_service = MatchmakingService::new(event_bus.clone());
+        tokio::spawn(async move { matchmaking_service.run().await });
+        // 等待 MatchmakingService 启动并订阅事件
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut event_receiver = event_bus.subscribe();
+
+        for (player_id, mode) in [
+            (1, MatchMode::Ranked),
+            // 玩家1改为匹配休闲赛, 离开排位赛队列
+            (1, MatchMode::Casual),
+            (2, MatchMode::Ranked),
+            (3, MatchMode::Casual),
+        ] {
+            event_bus.publish(ServerEvent::PlayerReadyForMatchmaking { player_id, mode });
+        }
+        // 断开连接的玩家离开匹配队列
+        event_bus.publish(ServerEvent::PlayerDisconnected { player_id: 2 });
+        event_bus.publish(ServerEvent::PlayerReadyForMatchmaking {
+            player_id: 4,
+            mode: MatchMode::Ranked,
+        });
+
+        let mut matches = vec![];
+        while let Ok(Ok(event)) =
+            tokio::time::timeout(Duration::from_millis(200), event_receiver.recv()).await
+        {
+            if let ServerEvent::MatchFound { players, mode } = event {
+                matches.push((players, mode));
+            }
+        }
+        assert_eq!(matches, vec![([1, 3], MatchMode::Casual)]);
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use common::{message::RoomAction, sprites::Sprite};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battle_rules::BattleRules,
+    room::{BattleResult, GameState},
+};
+
+/// 战斗回放
+///
+/// 记录复现一场战斗所需的全部输入: 随机数种子、对战规则、双方提交的精灵队伍以及按发生顺序记录的玩家行为。
+/// 使用与 `GameMessageCodec` 相同的 bincode 配置编码
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BattleReplay {
+    /// 房间战斗使用的随机数种子
+    pub seed: u64,
+    pub players: [u64; 2],
+    pub rules: BattleRules,
+    /// 双方提交的精灵队伍, 按提交顺序排列
+    pub teams: Vec<(u64, Vec<Sprite>)>,
+    /// 按发生顺序记录的玩家行为
+    pub inputs: Vec<ReplayInput>,
+    /// 战斗结果, 战斗结束后才有值
+    pub result: Option<BattleResult>,
+    /// 战斗结束时双方精灵的HP, 按提交队伍的顺序排列
+    pub end_hp: Vec<(u64, Vec<u16>)>,
+}
+
+/// 回放中记录的玩家行为, `timestamp` 为 Unix 毫秒时间戳
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplayInput {
+    /// 回合结算时双方的行为, 包括超时后自动填入的默认行为
+    Turn {
+        turn: u32,
+        actions: Vec<(u64, RoomAction)>,
+        timestamp: i64,
+    },
+    /// 回合结算之外切换上场的精灵, 如替换倒下的精灵或不占用回合的切换
+    Switch {
+        player_id: u64,
+        sprite_index: usize,
+        timestamp: i64,
+    },
+    /// 战斗结束, 逃跑、超时判负等不经过回合结算的结束也从这里复现
+    Finish {
+        result: BattleResult,
+        timestamp: i64,
+    },
+}
+
+impl BattleReplay {
+    pub fn new(seed: u64, players: [u64; 2], rules: BattleRules) -> Self {
+        Self {
+            seed,
+            players,
+            rules,
+            teams: Vec::new(),
+            inputs: Vec::new(),
+            result: None,
+            end_hp: Vec::new(),
+        }
+    }
+
+    /// 编码回放
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serde::encode_to_vec(
+            self,
+            bincode::config::standard(),
+        )?)
+    }
+
+    /// 解码回放
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let (replay, _) = bincode::serde::decode_from_slice(bytes, bincode::config::standard())?;
+        Ok(replay)
+    }
+
+    /// 使用战斗引擎重新模拟回放, 检查是否到达与记录相同的结束状态:
+    /// 战斗结果(包括结算的回合数)与双方精灵的HP
+    ///
+    /// 返回模拟结束时的战斗状态
+    pub fn verify(&self) -> anyhow::Result<GameState> {
+        let game_state = GameState::from_replay(self)?;
+        if game_state.result != self.result {
+            return Err(anyhow::anyhow!(
+                "回放模拟的战斗结果 {:?} 与记录的结果 {:?} 不一致",
+                game_state.result,
+                self.result
+            ));
+        }
+        let end_hp = game_state.team_hp();
+        if end_hp != self.end_hp {
+            return Err(anyhow::anyhow!(
+                "回放模拟结束时的精灵HP {:?} 与记录的HP {:?} 不一致",
+                end_hp,
+                self.end_hp
+            ));
+        }
+        Ok(game_state)
+    }
+}
+
+/// 保存回放到指定目录, 文件名为 `room_<房间ID>_<随机数种子>.replay`
+pub async fn save_replay(
+    dir: &Path,
+    room_id: u64,
+    replay: &BattleReplay,
+) -> anyhow::Result<PathBuf> {
+    tokio::fs::create_dir_all(dir).await?;
+    let path = dir.join(format!("room_{}_{}.replay", room_id, replay.seed));
+    tokio::fs::write(&path, replay.encode()?).await?;
+    Ok(path)
+}
+
+/// 读取回放文件
+pub async fn load_replay(path: &Path) -> anyhow::Result<BattleReplay> {
+    BattleReplay::decode(&tokio::fs::read(path).await?)
+}
@@ -1,16 +1,31 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::HashMap,
+    path::PathBuf,
+    time::Duration,
+};
 
+use chrono::Utc;
 use common::{
-    buff_effect::ExceptionEffect,
-    message::RoomAction,
-    sprites::{Sprite, skills::Skill},
+    message::{BattleEndReason, BattleEvent, RoomAction, RoomActionError, ServerPayload},
+    sprites::{
+        Sprite, VisibleSprite,
+        skills::{BattleStat, EffectTarget, STRUGGLE_SKILL_ID, Skill, SkillSpecialEffect},
+    },
 };
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
 use tokio::{sync::mpsc::Receiver, time::Instant};
 
 use crate::{
     actor::{ActorMessage, SystemMessage},
-    events::EventBus,
+    battle_rules::{BattleRules, TieBreak},
+    damage::{DamageResult, calculate_damage},
+    events::{EventBus, ServerEvent},
+    replay::{self, BattleReplay, ReplayInput},
     session::SessionManager,
+    stat_stage::{MAX_STAGE, StatStages},
+    status_effect::{SpriteKey, StatusEffects},
 };
 
 pub enum RoomActorMessage {
@@ -19,16 +34,36 @@ pub enum RoomActorMessage {
         sprite_team: Vec<Sprite>,
     },
     Close,
-    RoomAction(RoomAction),
+    RoomAction(RoomActionEnvelope),
+}
+
+/// 战斗结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BattleResult {
+    /// 胜者, 平局时为空
+    pub winner: Option<u64>,
+    pub reason: BattleEndReason,
+    /// 已结算的回合数
+    pub turns: u32,
+}
+
+/// 玩家提交的房间操作
+///
+/// 客户端提交的 [`RoomAction`] 不携带玩家ID，由 `PlayerActor` 使用会话中已认证的玩家ID封装后发给房间
+#[derive(Debug, Clone, Copy)]
+pub struct RoomActionEnvelope {
+    pub player_id: u64,
+    pub action: RoomAction,
 }
 
 pub struct RoomActor {
     room_id: u64,
     event_bus: EventBus,
-    players: [u64; 2],
     game_state: GameState,
     receiver: Receiver<RoomActorMessage>,
     session_manager: SessionManager,
+    /// 战斗结束时保存回放的目录, 为空时不保存
+    replay_dir: Option<PathBuf>,
 }
 
 impl RoomActor {
@@ -38,21 +73,34 @@ impl RoomActor {
         players: [u64; 2],
         receiver: Receiver<RoomActorMessage>,
         session_manager: SessionManager,
+        rules: BattleRules,
+        seed: u64,
     ) -> Self {
+        let mut game_state = GameState::new(players, rules, seed);
+        // 房间创建后开始等待双方提交精灵队伍
+        game_state
+            .pk_state
+            .start_waiting_teams(rules.team_timeout());
         Self {
             room_id,
             event_bus,
-            players,
-            game_state: GameState::default(),
+            game_state,
             receiver,
             session_manager,
+            replay_dir: None,
         }
     }
 
+    /// 设置战斗结束时保存回放的目录
+    pub fn with_replay_dir(mut self, replay_dir: Option<PathBuf>) -> Self {
+        self.replay_dir = replay_dir;
+        self
+    }
+
     pub async fn run(&mut self) {
         println!("房间 {} 的Actor正在运行", self.room_id);
-        // 每秒检查一次
-        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        // 按规则的间隔检查是否超时
+        let mut interval = tokio::time::interval(self.game_state.rules.tick());
         while !self.game_state.is_end() {
             tokio::select! {
                 _ = interval.tick() => {
@@ -63,24 +111,36 @@ impl RoomActor {
                 },
                 Some(msg) = self.receiver.recv() => {
                     match msg {
+                        // 只在等待提交队伍时接收精灵队伍, 战斗开始后重新提交会重置队伍与上场的精灵
                         RoomActorMessage::SpriteTeam {
                             player_id,
                             sprite_team,
-                        } if self.players.contains(&player_id) => {
+                        } if self.game_state.players.contains(&player_id)
+                            && matches!(
+                                self.game_state.pk_state,
+                                PKState::WaitingSpriteTeams { .. }
+                            ) =>
+                        {
                             println!("[RoomActor {}] 玩家 {} 提交了", self.room_id, player_id);
-                            // 1. 初始化玩家精灵队伍
-                            self.game_state.sprite_teams.insert(player_id, sprite_team);
-                            // 2. 设置精灵队伍的首精灵为当前上场精灵
-                            self.game_state.current_sprite_players.insert(player_id, 0);
-                            // 3. 等待双方精灵数据提交完成
-                            if self.game_state.is_teams_ready() {
-                                // 4. 通知双方精灵队伍已准备好，选择使用的技能TODO:
-                                // 5. 进入等待技能释放状态
-                                self.game_state.pk_state.start_waiting_skill();
+                            // 1. 按规则初始化玩家精灵队伍, 并设置首精灵为当前上场精灵
+                            if !self.game_state.submit_team(player_id, sprite_team) {
+                                println!(
+                                    "[RoomActor {}] 玩家 {} 没有符合规则的精灵",
+                                    self.room_id, player_id
+                                );
+                                continue;
+                            }
+                            // 2. 双方精灵数据提交完成后进入等待技能释放状态
+                            if self.game_state.start_battle() {
+                                // 3. 通知双方对战开始与第一回合开始
+                                self.notify_battle_start().await;
+                                self.notify_turn_start().await;
                             }
                         }
                         RoomActorMessage::RoomAction(room_action) => {
-                            self.handle_room_action(room_action).await;
+                            if let Err(e) = self.handle_room_action(room_action).await {
+                                println!("[RoomActor {}] 处理玩家操作失败: {:?}", self.room_id, e);
+                            }
                         }
                         RoomActorMessage::Close => {
                             println!("房间 {} 的Actor正在关闭", self.room_id);
@@ -91,208 +151,615 @@ impl RoomActor {
                 }
             }
         }
+        // 房间被提前关闭时没有胜者
+        let result = match self.game_state.result {
+            Some(result) => result,
+            None => self.game_state.finish(None, BattleEndReason::Closed),
+        };
+        self.announce_result(result).await;
+        self.save_replay().await;
         println!("房间 {} 的Actor已关闭", self.room_id);
     }
 
-    async fn handle_room_action(&mut self, room_action: RoomAction) -> anyhow::Result<()> {
+    async fn handle_room_action(&mut self, envelope: RoomActionEnvelope) -> anyhow::Result<()> {
+        let RoomActionEnvelope {
+            player_id,
+            action: room_action,
+        } = envelope;
+        if !self.game_state.players.contains(&player_id) {
+            return Err(anyhow::anyhow!("玩家 {} 不在房间中", player_id));
+        }
+        // 等待替换倒下的精灵时, 只能提交替换的精灵或者逃跑
+        if matches!(self.game_state.pk_state, PKState::WaitingReplacement { .. })
+            && !matches!(room_action, RoomAction::Escape)
+        {
+            match room_action {
+                RoomAction::SwitchSprite { sprite_index } => {
+                    self.handle_replacement(player_id, room_action, sprite_index)
+                        .await;
+                }
+                _ => {
+                    self.reject_room_action(player_id, room_action, RoomActionError::WrongPhase)
+                        .await;
+                }
+            }
+            return Ok(());
+        }
+        // 其余阶段(如等待提交队伍)只能逃跑
+        if !matches!(self.game_state.pk_state, PKState::WaitingSkillAttack { .. })
+            && !matches!(room_action, RoomAction::Escape)
+        {
+            self.reject_room_action(player_id, room_action, RoomActionError::WrongPhase)
+                .await;
+            return Ok(());
+        }
         match room_action {
-            RoomAction::SkillAttack {
-                player_id,
-                skill_id,
-            } => {
+            RoomAction::SkillAttack { skill_id } => {
                 println!(
                     "[RoomActor {}] 玩家 {} 攻击了技能 {:?}",
                     self.room_id, player_id, skill_id
                 );
-                // 1. 记录玩家提交的操作
-                self.game_state.room_actions.insert(player_id, room_action);
+                // 1. 检查上场精灵能否使用该技能, 被拒绝后可以在截止时间前重新提交
+                if let Err(reason) = self.game_state.check_skill_attack(player_id, skill_id) {
+                    self.reject_room_action(player_id, room_action, reason)
+                        .await;
+                    return Ok(());
+                }
+                // 2. 记录玩家提交的操作
+                self.game_state.submit_action(player_id, room_action);
                 // 2. 检查是否所有玩家都提交了操作
                 if self
                     .game_state
                     .is_room_actions_ready(self.get_target_player_id(player_id))
                 {
                     // 3. 处理玩家提交的操作
-                    self.handle_room_actions();
+                    self.handle_room_actions().await;
                 }
             }
-            RoomAction::SwitchSprite {
-                player_id,
-                sprite_index,
-            } => {
-                // 1. 切换上场的精灵
-                self.game_state
-                    .switch_current_sprite(player_id, sprite_index)?;
+            RoomAction::SwitchSprite { sprite_index } => {
+                // 1. 检查要切换的精灵是否可以上场, 真正的切换在回合结算时进行
+                if let Err(e) = self.game_state.check_switch_target(player_id, sprite_index) {
+                    println!(
+                        "[RoomActor {}] 玩家 {} 无法切换精灵: {:?}",
+                        self.room_id, player_id, e
+                    );
+                    self.reject_room_action(player_id, room_action, RoomActionError::InvalidSprite)
+                        .await;
+                    return Ok(());
+                }
+                // 切换精灵不占用回合时立即切换, 玩家还需要提交本回合的行为
+                if !self.game_state.rules.switch_costs_turn {
+                    self.game_state
+                        .switch_out_of_turn(player_id, sprite_index)?;
+                    self.broadcast(ServerPayload::SpriteReplaced {
+                        player_id,
+                        sprite_index,
+                    })
+                    .await;
+                    if self.game_state.is_room_actions_ready(player_id) {
+                        self.handle_room_actions().await;
+                    }
+                    return Ok(());
+                }
                 // 2. 记录玩家提交的操作
-                self.game_state.room_actions.insert(player_id, room_action);
+                self.game_state.submit_action(player_id, room_action);
                 // 2. 检查是否所有玩家都提交了操作
                 if self
                     .game_state
                     .is_room_actions_ready(self.get_target_player_id(player_id))
                 {
                     // 3. 处理玩家提交的操作
-                    self.handle_room_actions();
+                    self.handle_room_actions().await;
                 }
             }
-            RoomAction::UseItem { player_id, .. } => {
+            RoomAction::UseItem { .. } => {
+                if !self.game_state.rules.allow_items {
+                    self.reject_room_action(
+                        player_id,
+                        room_action,
+                        RoomActionError::ItemsNotAllowed,
+                    )
+                    .await;
+                    return Ok(());
+                }
                 // 1. 记录玩家提交的物品
-                self.game_state.room_actions.insert(player_id, room_action);
+                self.game_state.submit_action(player_id, room_action);
                 // 2. 检查是否所有玩家都提交了操作
                 if self
                     .game_state
                     .is_room_actions_ready(self.get_target_player_id(player_id))
                 {
                     // 3. 处理玩家提交的操作
-                    self.handle_room_actions();
+                    self.handle_room_actions().await;
                 }
             }
             RoomAction::CatchSprite => {
-                // 1. 判断是否可以捕获精灵,
-                if self.game_state.is_catch_sprite_ready() {
-                    // 2. 记录玩家提交的操作
+                // 1. 判断是否可以捕获精灵, 玩家之间的对战不能捕捉
+                if !self.game_state.is_catch_sprite_ready() {
+                    self.reject_room_action(
+                        player_id,
+                        room_action,
+                        RoomActionError::CatchNotAllowed,
+                    )
+                    .await;
+                    return Ok(());
+                }
+                // 2. 记录玩家提交的操作
+                self.game_state.submit_action(player_id, room_action);
+                // 3. 检查是否所有玩家都提交了操作
+                if self
+                    .game_state
+                    .is_room_actions_ready(self.get_target_player_id(player_id))
+                {
+                    self.handle_room_actions().await;
                 }
             }
-            RoomAction::Escape(player_id) => {
+            RoomAction::Escape => {
+                let target_player_id = self.get_target_player_id(player_id);
                 // 通知对方玩家逃跑了
                 self.session_manager
                     .send_message(
-                        self.get_target_player_id(player_id),
+                        target_player_id,
                         ActorMessage::SystemNotification(SystemMessage::Escape(player_id)),
                     )
                     .await;
                 // 结束战斗，关闭房间
-                self.game_state.pk_state.end_battle();
+                self.game_state
+                    .finish(Some(target_player_id), BattleEndReason::Escape);
             }
         }
         Ok(())
     }
 
-    fn handle_room_actions(&mut self) {}
-
-    async fn handle_skill_attack(&mut self, player_id: u64, skill_id: u64) {
-        // 1. 记录玩家提交的技能
-        // self.game_state.skill_players.insert(player_id, skill_id);
-        // // 需要等待双方都提交了技能，还要处理被控制无法攻击的情况，不用等待技能
-        // let other_player_id = if self.players[0] == player_id {
-        //     self.players[1]
-        // } else {
-        //     self.players[0]
-        // };
-        // let mut is_await = true;
-        // if let Some(exception_effect) = self.game_state.exception_effects.get(&player_id)
-        //     && matches!(exception_effect, ExceptionEffect::Numbness)
-        //     && matches!(exception_effect, ExceptionEffect::Freeze)
-        // {
-        //     is_await = false;
-        // }
-        // if is_await {
-        //     if self.game_state.is_skill_ready() {
-        //         let Some(battle_sprite_a) = self.sprite_and_skill(0) else {
-        //             return;
-        //         };
-        //         let Some(battle_sprite_b) = self.sprite_and_skill(1) else {
-        //             return;
-        //         };
-        //     }
-        // } else {
-        //     // 不需要等待对方提交技能，直接处理
-        // }
-    }
-
-    fn sprite_and_skill(&mut self, index: usize) -> Option<RoomAction> {
-        // 1. 获取双方玩家的id
-        let player_id = self.players[index];
-        // 2. 获取当前上场精灵
-        let Some(current_sprite) = self.current_sprite(player_id) else {
-            return None;
-        };
-        // 是否需要判断该技能是玩家宠物配置的几个技能中的一个TODO:
-        // 4. 获取玩家提交的操作
-        let Some(room_action) = self.game_state.room_actions.remove(&player_id) else {
-            return None;
-        };
-        // let Some(skill) = current_sprite.skills.iter().find(|s| s.id == *skill_id) else {
-        //     return None;
-        // };
-        // // 5. 获取玩家提交的技能
-        // Some(BattleSprite {
-        //     sprite: current_sprite.clone(),
-        //     skill: skill.clone(),
-        // })
-        Some(room_action)
-    }
-
-    fn current_sprite(&self, player_id: u64) -> Option<&Sprite> {
-        let Some(sprite_team) = self.game_state.sprite_teams.get(&player_id) else {
-            return None;
-        };
-        let Some(current_sprite_index) = self.game_state.current_sprite_players.get(&player_id)
-        else {
-            return None;
-        };
-        Some(&sprite_team[*current_sprite_index])
+    /// 结算双方提交的操作并进入下一回合
+    async fn handle_room_actions(&mut self) {
+        println!("[RoomActor {}] 开始结算回合", self.room_id);
+        let turn = self.game_state.turn;
+        let events = self.game_state.resolve_turn();
+        self.broadcast(ServerPayload::TurnResult { turn, events })
+            .await;
+        match &self.game_state.pk_state {
+            // 战斗结束, 退出房间时通知双方结果
+            PKState::Ended => {}
+            // 上场精灵倒下的玩家需要选择替换的精灵
+            PKState::WaitingReplacement { players, deadline } => {
+                for player_id in players {
+                    self.session_manager
+                        .send_to_player(
+                            *player_id,
+                            ServerPayload::ForceSwitch {
+                                deadline: unix_millis(*deadline),
+                            },
+                        )
+                        .await;
+                }
+            }
+            _ => self.notify_turn_start().await,
+        }
+    }
+
+    /// 处理玩家替换倒下的精灵, 双方都替换完成后开始下一回合
+    async fn handle_replacement(
+        &mut self,
+        player_id: u64,
+        room_action: RoomAction,
+        sprite_index: usize,
+    ) {
+        match self.game_state.replace_sprite(player_id, sprite_index) {
+            Ok(()) => {
+                self.broadcast(ServerPayload::SpriteReplaced {
+                    player_id,
+                    sprite_index,
+                })
+                .await;
+                self.notify_turn_start().await;
+            }
+            Err(reason) => {
+                self.reject_room_action(player_id, room_action, reason)
+                    .await;
+            }
+        }
     }
 
     /// 处理超时
     async fn handle_timeout(&mut self) {
         match self.game_state.pk_state {
             PKState::WaitingSpriteTeams { .. } => {
-                // 超时处理：结束战斗, 并通知双方TODO:
-                self.game_state.pk_state = PKState::Ended;
+                // 超时处理：已提交队伍的一方获胜, 退出房间时通知双方结果
+                self.game_state.forfeit_missing_teams();
             }
             PKState::WaitingSkillAttack { .. } => {
-                // 超时处理：TODO:释放默认技能，进入下一个回合
-                self.game_state.pk_state.next_turn();
+                // 超时处理：未提交的玩家释放默认技能, 连续超时过多的玩家判负
+                self.game_state.fill_timeout_actions();
+                if !self.game_state.is_end() {
+                    self.handle_room_actions().await;
+                }
+            }
+            PKState::WaitingReplacement { .. } => {
+                // 超时处理：自动替换为队伍中第一只可以上场的精灵
+                for (player_id, sprite_index) in self.game_state.auto_replace() {
+                    self.broadcast(ServerPayload::SpriteReplaced {
+                        player_id,
+                        sprite_index,
+                    })
+                    .await;
+                }
+                self.notify_turn_start().await;
             }
             _ => {}
         }
     }
 
-    fn get_target_player_id(&self, player_id: u64) -> u64 {
-        if self.players[0] == player_id {
-            self.players[1]
-        } else {
-            self.players[0]
+    /// 通知玩家提交的操作被拒绝
+    async fn reject_room_action(
+        &self,
+        player_id: u64,
+        action: RoomAction,
+        reason: RoomActionError,
+    ) {
+        println!(
+            "[RoomActor {}] 玩家 {} 的操作 {:?} 被拒绝: {:?}",
+            self.room_id, player_id, action, reason
+        );
+        self.session_manager
+            .send_to_player(
+                player_id,
+                ServerPayload::RoomActionRejected { action, reason },
+            )
+            .await;
+    }
+
+    /// 通知双方对战开始, 每个玩家只能看到对手精灵的公开信息
+    async fn notify_battle_start(&self) {
+        for player_id in self.game_state.players {
+            let opponent_id = self.get_target_player_id(player_id);
+            let opponent_team = self
+                .game_state
+                .sprite_teams
+                .get(&opponent_id)
+                .map(|team| team.iter().map(VisibleSprite::from).collect())
+                .unwrap_or_default();
+            self.session_manager
+                .send_to_player(
+                    player_id,
+                    ServerPayload::BattleStart {
+                        room_id: self.room_id,
+                        opponent_id,
+                        opponent_team,
+                    },
+                )
+                .await;
         }
     }
-}
 
-#[derive(Debug)]
-pub struct BattleSprite {
-    sprite: Sprite,
-    skill: Skill,
+    /// 处于等待技能释放状态时, 通知双方新回合开始
+    async fn notify_turn_start(&self) {
+        if let PKState::WaitingSkillAttack { turn_deadline } = self.game_state.pk_state {
+            self.broadcast(ServerPayload::TurnStart {
+                turn: self.game_state.turn,
+                deadline: unix_millis(turn_deadline),
+            })
+            .await;
+        }
+    }
+
+    /// 通知双方战斗结果, 并发布战斗结束事件, 由 `GameCoordinator` 回收房间与释放玩家
+    async fn announce_result(&self, result: BattleResult) {
+        println!(
+            "[RoomActor {}] 战斗结束, 胜者: {:?}, 原因: {:?}, 回合数: {}",
+            self.room_id, result.winner, result.reason, result.turns
+        );
+        self.broadcast(ServerPayload::BattleEnd {
+            winner: result.winner,
+            reason: result.reason,
+        })
+        .await;
+        self.event_bus.publish(ServerEvent::BattleFinished {
+            room_id: self.room_id,
+            players: self.game_state.players,
+            result,
+        });
+    }
+
+    /// 保存战斗回放
+    async fn save_replay(&self) {
+        let Some(replay_dir) = &self.replay_dir else {
+            return;
+        };
+        match replay::save_replay(replay_dir, self.room_id, &self.game_state.replay).await {
+            Ok(path) => println!("[RoomActor {}] 回放已保存到 {:?}", self.room_id, path),
+            Err(e) => println!("[RoomActor {}] 保存回放失败: {:?}", self.room_id, e),
+        }
+    }
+
+    /// 发送消息给房间内的双方玩家
+    async fn broadcast(&self, payload: ServerPayload) {
+        for player_id in self.game_state.players {
+            self.session_manager
+                .send_to_player(player_id, payload.clone())
+                .await;
+        }
+    }
+
+    fn get_target_player_id(&self, player_id: u64) -> u64 {
+        self.game_state.opponent_of(player_id)
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct GameState {
+    /// 房间内的双方玩家
+    pub players: [u64; 2],
     /// 玩家精灵队伍
     pub sprite_teams: HashMap<u64, Vec<Sprite>>,
-    /// 异常效果
-    pub exception_effects: HashMap<u64, ExceptionEffect>,
+    /// 异常状态, 按精灵记录
+    pub status_effects: StatusEffects,
+    /// 能力等级, 按精灵记录
+    pub stat_stages: StatStages,
     /// 玩家行为
     pub room_actions: HashMap<u64, RoomAction>,
     /// 当前上场精灵索引
     pub current_sprite_players: HashMap<u64, usize>,
     /// 战斗状态
     pub pk_state: PKState,
+    /// 当前回合数, 从1开始
+    pub turn: u32,
+    /// 玩家连续超时未提交行为的回合数
+    pub afk_turns: HashMap<u64, u32>,
+    /// 对战规则
+    pub rules: BattleRules,
+    /// 随机数种子, 相同的种子与相同的行为序列总是得到相同的战斗结果
+    pub seed: u64,
+    /// 战斗中所有的随机判定都使用该随机数生成器
+    rng: StdRng,
+    /// 战斗回放, 随战斗进行记录
+    pub replay: BattleReplay,
+    /// 战斗结果, 战斗结束后才有值
+    pub result: Option<BattleResult>,
 }
 
 impl GameState {
+    pub fn new(players: [u64; 2], rules: BattleRules, seed: u64) -> Self {
+        Self {
+            players,
+            sprite_teams: HashMap::new(),
+            status_effects: StatusEffects::new(),
+            stat_stages: StatStages::new(),
+            room_actions: HashMap::new(),
+            current_sprite_players: HashMap::new(),
+            pk_state: PKState::default(),
+            turn: 1,
+            afk_turns: HashMap::new(),
+            rules,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            replay: BattleReplay::new(seed, players, rules),
+            result: None,
+        }
+    }
+
+    /// 按回放记录的输入重新模拟战斗
+    pub fn from_replay(replay: &BattleReplay) -> anyhow::Result<Self> {
+        let mut game_state = GameState::new(replay.players, replay.rules, replay.seed);
+        game_state
+            .pk_state
+            .start_waiting_teams(replay.rules.team_timeout());
+        for (player_id, sprite_team) in &replay.teams {
+            game_state.submit_team(*player_id, sprite_team.clone());
+        }
+        game_state.start_battle();
+        for input in &replay.inputs {
+            // 战斗结束后只会记录结束本身, 模拟提前结束说明与记录的战斗不一致
+            if game_state.is_end() && !matches!(input, ReplayInput::Finish { .. }) {
+                return Err(anyhow::anyhow!(
+                    "回放模拟的战斗在第 {} 回合提前结束",
+                    game_state.turn - 1
+                ));
+            }
+            match input {
+                ReplayInput::Turn { turn, actions, .. } => {
+                    if *turn != game_state.turn {
+                        return Err(anyhow::anyhow!(
+                            "回放记录的第 {} 回合与模拟的第 {} 回合不一致",
+                            turn,
+                            game_state.turn
+                        ));
+                    }
+                    game_state.room_actions = actions.iter().copied().collect();
+                    game_state.resolve_turn();
+                }
+                ReplayInput::Switch {
+                    player_id,
+                    sprite_index,
+                    ..
+                } => {
+                    let result =
+                        if matches!(game_state.pk_state, PKState::WaitingReplacement { .. }) {
+                            game_state
+                                .replace_sprite(*player_id, *sprite_index)
+                                .map_err(|e| anyhow::anyhow!("{:?}", e))
+                        } else {
+                            game_state.switch_out_of_turn(*player_id, *sprite_index)
+                        };
+                    if let Err(e) = result {
+                        println!("[GameState] 回放中玩家 {} 切换精灵失败: {:?}", player_id, e);
+                    }
+                }
+                // 战斗引擎自己判定的结束必须由模拟复现, 只有逃跑、判负等外部原因的结束从记录中复现
+                ReplayInput::Finish { result, .. } => match result.reason {
+                    BattleEndReason::AllFainted | BattleEndReason::TurnLimit => {
+                        if !game_state.is_end() {
+                            return Err(anyhow::anyhow!(
+                                "回放记录战斗在第 {} 回合因 {:?} 结束, 但模拟的战斗没有结束",
+                                result.turns,
+                                result.reason
+                            ));
+                        }
+                    }
+                    BattleEndReason::Escape
+                    | BattleEndReason::Forfeit
+                    | BattleEndReason::Timeout
+                    | BattleEndReason::Closed => {
+                        if !game_state.is_end() {
+                            game_state.finish(result.winner, result.reason);
+                        }
+                    }
+                },
+            }
+        }
+        Ok(game_state)
+    }
+
+    /// 获取对手的玩家ID
+    fn opponent_of(&self, player_id: u64) -> u64 {
+        if self.players[0] == player_id {
+            self.players[1]
+        } else {
+            self.players[0]
+        }
+    }
+
+    /// 记录玩家提交的精灵队伍, 超过等级上限的精灵不能出战, 超出队伍数量上限的精灵被忽略
+    ///
+    /// 没有可以出战的精灵时返回 `false`
+    pub fn submit_team(&mut self, player_id: u64, sprite_team: Vec<Sprite>) -> bool {
+        let sprite_team: Vec<Sprite> = sprite_team
+            .into_iter()
+            .filter(|sprite| sprite.level <= self.rules.level_cap)
+            .take(self.rules.team_size)
+            .collect();
+        if sprite_team.is_empty() {
+            return false;
+        }
+        self.replay.teams.push((player_id, sprite_team.clone()));
+        self.sprite_teams.insert(player_id, sprite_team);
+        self.current_sprite_players.insert(player_id, 0);
+        true
+    }
+
+    /// 双方都提交了精灵队伍后开始战斗, 进入等待技能释放状态
+    pub fn start_battle(&mut self) -> bool {
+        if !self.is_teams_ready() {
+            return false;
+        }
+        self.pk_state.start_waiting_teams(self.rules.team_timeout());
+        self.pk_state.start_waiting_skill(self.rules.turn_timeout());
+        true
+    }
+
     /// 判断双方是否都提交了精灵队伍
     fn is_teams_ready(&mut self) -> bool {
         self.sprite_teams.len() == 2
     }
 
+    /// 等待队伍超时, 已提交队伍的一方不战而胜, 双方都没有提交时为平局
+    fn forfeit_missing_teams(&mut self) -> BattleResult {
+        let submitted = self
+            .players
+            .into_iter()
+            .filter(|player_id| self.sprite_teams.contains_key(player_id))
+            .collect::<Vec<_>>();
+        match submitted.as_slice() {
+            [winner] => self.finish(Some(*winner), BattleEndReason::Forfeit),
+            _ => self.finish(None, BattleEndReason::Timeout),
+        }
+    }
+
+    /// 记录玩家主动提交的行为, 并重置连续超时的回合数
+    pub fn submit_action(&mut self, player_id: u64, room_action: RoomAction) {
+        self.room_actions.insert(player_id, room_action);
+        self.afk_turns.remove(&player_id);
+    }
+
+    /// 上场精灵的默认行为: 使用第一个还有PP的技能, 所有技能的PP都耗尽时使用挣扎
+    ///
+    /// 精灵倒下或被控制时没有默认行为
+    pub fn default_action(&self, player_id: u64) -> Option<RoomAction> {
+        let key = self.current_key(player_id)?;
+        let sprite = self.current_sprite(player_id)?;
+        if sprite.hp == 0 || !self.status_effects.can_act(key) {
+            return None;
+        }
+        let skill_id = sprite
+            .skills
+            .iter()
+            .find(|skill| skill.pp > 0)
+            .map_or(STRUGGLE_SKILL_ID, |skill| skill.id);
+        Some(RoomAction::SkillAttack { skill_id })
+    }
+
+    /// 等待技能超时, 为可以行动却没有提交行为的玩家填入默认行为
+    ///
+    /// 连续超时的回合数达到上限的玩家判负, 双方同时达到上限时为平局
+    fn fill_timeout_actions(&mut self) {
+        let mut forfeited = Vec::new();
+        for player_id in self.players {
+            if self.room_actions.contains_key(&player_id) || !self.can_act(player_id) {
+                continue;
+            }
+            let afk_turns = self.afk_turns.entry(player_id).or_default();
+            *afk_turns += 1;
+            if *afk_turns >= self.rules.max_afk_turns {
+                forfeited.push(player_id);
+            } else if let Some(action) = self.default_action(player_id) {
+                self.room_actions.insert(player_id, action);
+            }
+        }
+        match forfeited.as_slice() {
+            [] => {}
+            [loser] => {
+                let winner = self.opponent_of(*loser);
+                self.finish(Some(winner), BattleEndReason::Forfeit);
+            }
+            _ => {
+                self.finish(None, BattleEndReason::Forfeit);
+            }
+        }
+    }
+
     /// 判断双方是否都提交了行为
-    fn is_room_actions_ready(&mut self, another_player_id: u64) -> bool {
-        // 当对方被控制无法操作时, 则无需等待另一个玩家
-        if let Some(exception_effect) = self.exception_effects.get(&another_player_id)
-            && matches!(exception_effect, ExceptionEffect::Numbness)
-            && matches!(exception_effect, ExceptionEffect::Freeze)
-        {
+    ///
+    /// 对方本回合无法行动时, 无需等待对方提交行为
+    fn is_room_actions_ready(&self, another_player_id: u64) -> bool {
+        if self.room_actions.len() == 2 {
             return true;
         }
-        // 没有被控制, 则需要等待另一个玩家提交行为
-        self.room_actions.len() == 2
+        !self.room_actions.is_empty() && !self.can_act(another_player_id)
+    }
+
+    /// 玩家上场的精灵本回合能否行动
+    ///
+    /// 精灵倒下、受到控制效果、所有技能的PP都已耗尽时无法行动
+    pub fn can_act(&self, player_id: u64) -> bool {
+        let (Some(key), Some(sprite)) =
+            (self.current_key(player_id), self.current_sprite(player_id))
+        else {
+            return false;
+        };
+        sprite.hp > 0
+            && self.status_effects.can_act(key)
+            && sprite.skills.iter().any(|skill| skill.pp > 0)
+    }
+
+    /// 检查玩家上场的精灵能否使用技能
+    ///
+    /// 精灵需要能够行动、学会了该技能且技能还有PP
+    fn check_skill_attack(&self, player_id: u64, skill_id: u64) -> Result<(), RoomActionError> {
+        let (Some(key), Some(sprite)) =
+            (self.current_key(player_id), self.current_sprite(player_id))
+        else {
+            return Err(RoomActionError::CannotAct);
+        };
+        if sprite.hp == 0 || !self.status_effects.can_act(key) {
+            return Err(RoomActionError::CannotAct);
+        }
+        let Some(skill) = sprite.skills.iter().find(|skill| skill.id == skill_id) else {
+            return Err(RoomActionError::UnknownSkill);
+        };
+        if skill.pp == 0 {
+            return Err(RoomActionError::NoPP);
+        }
+        Ok(())
     }
 
     /// 判断双方是否都提交了行为 TODO:
@@ -301,28 +768,621 @@ impl GameState {
         false
     }
 
-    fn is_end(&self) -> bool {
+    pub fn is_end(&self) -> bool {
         matches!(self.pk_state, PKState::Ended)
     }
 
-    fn switch_current_sprite(&mut self, player_id: u64, sprite_index: usize) -> anyhow::Result<()> {
-        // 检查目标精灵的HP值是否大于0
+    /// 获取玩家当前上场的精灵
+    pub fn current_sprite(&self, player_id: u64) -> Option<&Sprite> {
+        let sprite_team = self.sprite_teams.get(&player_id)?;
+        let current_sprite_index = self.current_sprite_players.get(&player_id)?;
+        sprite_team.get(*current_sprite_index)
+    }
+
+    /// 玩家当前上场精灵的标识
+    fn current_key(&self, player_id: u64) -> Option<SpriteKey> {
+        self.current_sprite_players
+            .get(&player_id)
+            .map(|sprite_index| (player_id, *sprite_index))
+    }
+
+    /// 计入异常状态与能力等级影响后的上场精灵面板
+    fn effective_sprite(&self, player_id: u64) -> Option<Sprite> {
+        let key = self.current_key(player_id)?;
+        let mut sprite = self.current_sprite(player_id)?.clone();
+        let attack_multiplier = self.status_effects.attack_multiplier(key);
+        let speed_multiplier = self.status_effects.speed_multiplier(key);
+        sprite.phy_atk = (sprite.phy_atk as f32 * attack_multiplier) as u16;
+        sprite.mag_atk = (sprite.mag_atk as f32 * attack_multiplier) as u16;
+        sprite.speed = (sprite.speed as f32 * speed_multiplier) as u16;
+        self.stat_stages.apply(key, &mut sprite);
+        Some(sprite)
+    }
+
+    /// 玩家上场的精灵倒下, 且还有其他可以上场的精灵
+    fn needs_replacement(&self, player_id: u64) -> bool {
+        self.current_sprite(player_id)
+            .is_some_and(|sprite| sprite.hp == 0)
+            && self
+                .sprite_teams
+                .get(&player_id)
+                .is_some_and(|team| team.iter().any(|sprite| sprite.hp > 0))
+    }
+
+    /// 玩家的精灵是否全部倒下
+    fn is_defeated(&self, player_id: u64) -> bool {
+        self.sprite_teams
+            .get(&player_id)
+            .is_some_and(|team| team.iter().all(|sprite| sprite.hp == 0))
+    }
+
+    /// 一方精灵全部倒下时的战斗结果, 双方同时全部倒下为平局
+    fn battle_result(&self) -> Option<BattleResult> {
+        let defeated: Vec<u64> = self
+            .players
+            .into_iter()
+            .filter(|player_id| self.is_defeated(*player_id))
+            .collect();
+        let winner = match defeated.as_slice() {
+            [] => return None,
+            [loser] => Some(self.opponent_of(*loser)),
+            _ => None,
+        };
+        Some(BattleResult {
+            winner,
+            reason: BattleEndReason::AllFainted,
+            turns: self.turn - 1,
+        })
+    }
+
+    /// 达到回合数上限时的胜者, 按规则比较双方剩余的精灵数量或HP比例, 相同时为平局
+    fn tie_break_winner(&self) -> Option<u64> {
+        let [player_a, player_b] = self.players;
+        let (score_a, score_b) = match self.rules.tie_break {
+            TieBreak::Draw => return None,
+            TieBreak::RemainingSprites => (
+                self.remaining_sprites(player_a) as f32,
+                self.remaining_sprites(player_b) as f32,
+            ),
+            TieBreak::RemainingHp => (
+                self.remaining_hp_ratio(player_a),
+                self.remaining_hp_ratio(player_b),
+            ),
+        };
+        match score_a.total_cmp(&score_b) {
+            Ordering::Greater => Some(player_a),
+            Ordering::Less => Some(player_b),
+            Ordering::Equal => None,
+        }
+    }
+
+    /// 玩家还可以战斗的精灵数量
+    fn remaining_sprites(&self, player_id: u64) -> usize {
+        self.sprite_teams
+            .get(&player_id)
+            .map_or(0, |team| team.iter().filter(|sprite| sprite.hp > 0).count())
+    }
+
+    /// 玩家队伍剩余HP占最大HP的比例
+    fn remaining_hp_ratio(&self, player_id: u64) -> f32 {
+        let Some(team) = self.sprite_teams.get(&player_id) else {
+            return 0.0;
+        };
+        let (hp, max_hp) = team.iter().fold((0u32, 0u32), |(hp, max_hp), sprite| {
+            (hp + sprite.hp as u32, max_hp + sprite.max_hp as u32)
+        });
+        if max_hp == 0 {
+            0.0
+        } else {
+            hp as f32 / max_hp as f32
+        }
+    }
+
+    /// 结束战斗并记录结果
+    fn finish(&mut self, winner: Option<u64>, reason: BattleEndReason) -> BattleResult {
+        let result = BattleResult {
+            winner,
+            reason,
+            turns: self.turn - 1,
+        };
+        self.result = Some(result);
+        self.pk_state.end_battle();
+        self.replay.result = Some(result);
+        self.replay.end_hp = self.team_hp();
+        self.replay.inputs.push(ReplayInput::Finish {
+            result,
+            timestamp: Utc::now().timestamp_millis(),
+        });
+        result
+    }
+
+    /// 双方精灵的HP, 按玩家顺序排列
+    pub fn team_hp(&self) -> Vec<(u64, Vec<u16>)> {
+        self.players
+            .iter()
+            .map(|player_id| {
+                let hp = self
+                    .sprite_teams
+                    .get(player_id)
+                    .map(|team| team.iter().map(|sprite| sprite.hp).collect())
+                    .unwrap_or_default();
+                (*player_id, hp)
+            })
+            .collect()
+    }
+
+    fn current_sprite_mut(&mut self, player_id: u64) -> Option<&mut Sprite> {
+        let current_sprite_index = *self.current_sprite_players.get(&player_id)?;
+        self.sprite_teams
+            .get_mut(&player_id)?
+            .get_mut(current_sprite_index)
+    }
+
+    /// 结算回合
+    ///
+    /// 1. 按照行为优先级、技能优先级、精灵速度决定双方的出手顺序, 完全相同时随机决定
+    /// 2. 依次执行双方的行为
+    /// 3. 结算双方上场精灵的异常状态
+    /// 4. 清空本回合提交的行为, 进入下一回合
+    /// 5. 判断胜负以及是否需要替换倒下的精灵
+    ///
+    /// 返回本回合按发生顺序记录的战斗事件
+    pub fn resolve_turn(&mut self) -> Vec<BattleEvent> {
+        // 按玩家顺序取出行为, 保证回放记录的顺序是确定的
+        let mut actions: Vec<(u64, RoomAction)> = self
+            .players
+            .iter()
+            .filter_map(|player_id| {
+                self.room_actions
+                    .remove(player_id)
+                    .map(|action| (*player_id, action))
+            })
+            .collect();
+        self.replay.inputs.push(ReplayInput::Turn {
+            turn: self.turn,
+            actions: actions.clone(),
+            timestamp: Utc::now().timestamp_millis(),
+        });
+        // 所有技能的PP都耗尽的精灵无需等待提交, 自动使用挣扎
+        for player_id in self.players {
+            if actions.iter().any(|(id, _)| *id == player_id) {
+                continue;
+            }
+            if let Some(
+                action @ RoomAction::SkillAttack {
+                    skill_id: STRUGGLE_SKILL_ID,
+                },
+            ) = self.default_action(player_id)
+            {
+                actions.push((player_id, action));
+            }
+        }
+        let mut ordered: Vec<_> = actions
+            .into_iter()
+            .map(|(player_id, action)| (self.action_order(player_id, &action), player_id, action))
+            .collect();
+        ordered.sort_by_key(|(order, ..)| Reverse(*order));
+        // 出手顺序完全相同时使用房间的随机数决定先后, 避免先进入匹配队列的玩家总是先出手
+        if let [first, second] = ordered.as_mut_slice()
+            && first.0 == second.0
+            && self.rng.random_bool(0.5)
+        {
+            std::mem::swap(first, second);
+        }
+        let actions: Vec<(u64, RoomAction)> = ordered
+            .into_iter()
+            .map(|(_, player_id, action)| (player_id, action))
+            .collect();
+
+        let mut events = Vec::new();
+        for (player_id, action) in actions {
+            self.execute_action(player_id, action, &mut events);
+        }
+        for player_id in self.players {
+            let Some(key) = self.current_key(player_id) else {
+                continue;
+            };
+            let fainted = self
+                .sprite_teams
+                .get_mut(&player_id)
+                .and_then(|team| team.get_mut(key.1))
+                .is_some_and(|sprite| self.status_effects.tick(key, sprite, &mut events));
+            if fainted {
+                self.faint(key, &mut events);
+            }
+        }
+
+        self.room_actions.clear();
+        self.turn += 1;
+        // 5. 一方精灵全部倒下时战斗结束, 否则上场精灵倒下的玩家需要替换精灵
+        if let Some(result) = self.battle_result() {
+            self.finish(result.winner, result.reason);
+            return events;
+        }
+        // 达到回合数上限时按规则判定胜负
+        if self.turn > self.rules.max_turns {
+            let winner = self.tie_break_winner();
+            self.finish(winner, BattleEndReason::TurnLimit);
+            return events;
+        }
+        let replacements: Vec<u64> = self
+            .players
+            .into_iter()
+            .filter(|player_id| self.needs_replacement(*player_id))
+            .collect();
+        if replacements.is_empty() {
+            self.pk_state.next_turn(self.rules.turn_timeout());
+        } else {
+            self.pk_state
+                .start_waiting_replacement(replacements, self.rules.replacement_timeout());
+        }
+        events
+    }
+
+    /// 行为的出手顺序, 值越大越先出手: (行为优先级, 技能优先级, 精灵速度)
+    fn action_order(&self, player_id: u64, action: &RoomAction) -> (u8, i8, u16) {
+        let current_sprite = self.current_sprite(player_id);
+        let skill_priority = match action {
+            RoomAction::SkillAttack { skill_id, .. } => current_sprite
+                .and_then(|sprite| sprite.skills.iter().find(|skill| skill.id == *skill_id))
+                .map_or(0, |skill| skill.priority),
+            _ => 0,
+        };
+        (
+            action_priority(action),
+            skill_priority,
+            self.effective_sprite(player_id)
+                .map_or(0, |sprite| sprite.speed),
+        )
+    }
+
+    fn execute_action(
+        &mut self,
+        player_id: u64,
+        action: RoomAction,
+        events: &mut Vec<BattleEvent>,
+    ) {
+        match action {
+            RoomAction::SkillAttack { skill_id, .. } => {
+                self.execute_skill_attack(player_id, skill_id, events);
+            }
+            RoomAction::SwitchSprite { sprite_index, .. } => {
+                match self.switch_current_sprite(player_id, sprite_index) {
+                    Ok(()) => events.push(BattleEvent::Switched {
+                        player_id,
+                        sprite_index,
+                    }),
+                    Err(e) => {
+                        println!("[GameState] 玩家 {} 切换精灵失败: {:?}", player_id, e)
+                    }
+                }
+            }
+            RoomAction::UseItem { item_id, .. } => {
+                // 道具系统尚未实现TODO:
+                println!("[GameState] 玩家 {} 使用了道具 {}", player_id, item_id);
+            }
+            RoomAction::CatchSprite | RoomAction::Escape => {}
+        }
+    }
+
+    /// 执行技能攻击, 对对方当前上场的精灵造成伤害
+    fn execute_skill_attack(
+        &mut self,
+        player_id: u64,
+        skill_id: u64,
+        events: &mut Vec<BattleEvent>,
+    ) {
+        let target_player_id = self.opponent_of(player_id);
+        let (Some(key), Some(attacker)) = (
+            self.current_key(player_id),
+            self.effective_sprite(player_id),
+        ) else {
+            return;
+        };
+        // 本回合已经被击倒的精灵无法出手
+        if attacker.hp == 0 {
+            return;
+        }
+        // 被控制的精灵无法出手
+        if let Some(effect) = self.status_effects.control_effect(key) {
+            events.push(BattleEvent::CannotAct { player_id, effect });
+            return;
+        }
+        // 所有技能的PP都耗尽时才能使用挣扎
+        let struggle = Skill::struggle();
+        let is_struggle =
+            skill_id == STRUGGLE_SKILL_ID && attacker.skills.iter().all(|skill| skill.pp == 0);
+        let skill = if is_struggle {
+            &struggle
+        } else {
+            let Some(skill) = attacker.skills.iter().find(|skill| skill.id == skill_id) else {
+                println!(
+                    "[GameState] 玩家 {} 的精灵 {} 没有技能 {}",
+                    player_id, attacker.id, skill_id
+                );
+                return;
+            };
+            skill
+        };
+        if skill.pp == 0 && !is_struggle {
+            println!(
+                "[GameState] 玩家 {} 的精灵 {} 的技能 {} PP不足",
+                player_id, attacker.id, skill_id
+            );
+            return;
+        }
+        let Some(defender) = self.effective_sprite(target_player_id) else {
+            return;
+        };
+        // 消耗技能的PP
+        if let Some(skill) = self
+            .current_sprite_mut(player_id)
+            .and_then(|sprite| sprite.skills.iter_mut().find(|skill| skill.id == skill_id))
+        {
+            skill.pp -= 1;
+        }
+        events.push(BattleEvent::SkillUsed {
+            player_id,
+            skill_id,
+        });
+        // 未命中时技能没有任何效果, PP照常消耗
+        if !roll_chance(&mut self.rng, skill.accuracy) {
+            events.push(BattleEvent::Miss {
+                player_id,
+                skill_id,
+            });
+            return;
+        }
+        // 威力为0的变化技能不造成伤害
+        if skill.power > 0 {
+            self.deal_damage(target_player_id, &attacker, &defender, skill, events);
+        }
+        // 挣扎的反伤
+        if is_struggle {
+            self.take_damage(player_id, attacker.max_hp / 4, 1.0, false, events);
+        }
+        if let Some(special_effect) = skill.special_effect {
+            self.apply_special_effect(player_id, special_effect, events);
+        }
+    }
+
+    /// 计算技能伤害并扣除对方上场精灵的HP
+    fn deal_damage(
+        &mut self,
+        target_player_id: u64,
+        attacker: &Sprite,
+        defender: &Sprite,
+        skill: &Skill,
+        events: &mut Vec<BattleEvent>,
+    ) {
+        let DamageResult {
+            multiplier,
+            is_critical,
+            damage,
+        } = calculate_damage(attacker, defender, skill, &mut self.rng);
+        self.take_damage(target_player_id, damage, multiplier, is_critical, events);
+    }
+
+    /// 扣除玩家上场精灵的HP, 精灵倒下时清除异常状态与能力等级
+    fn take_damage(
+        &mut self,
+        target_player_id: u64,
+        damage: u16,
+        multiplier: f32,
+        is_critical: bool,
+        events: &mut Vec<BattleEvent>,
+    ) {
+        let sprite_index = self.current_sprite_players[&target_player_id];
+        if let Some(defender) = self.current_sprite_mut(target_player_id) {
+            defender.hp = defender.hp.saturating_sub(damage);
+            println!(
+                "[GameState] 玩家 {} 的精灵 {} 受到 {} 点伤害(克制倍数: {}, 暴击: {}), 剩余HP {}",
+                target_player_id, defender.id, damage, multiplier, is_critical, defender.hp
+            );
+            let remaining_hp = defender.hp;
+            events.push(BattleEvent::Damage {
+                player_id: target_player_id,
+                sprite_index,
+                damage,
+                multiplier,
+                is_critical,
+                remaining_hp,
+            });
+            if remaining_hp == 0 {
+                self.faint((target_player_id, sprite_index), events);
+            }
+        }
+    }
+
+    /// 精灵倒下: 清除异常状态与能力等级, 无论是被技能击倒还是因持续伤害倒下
+    fn faint(&mut self, key: SpriteKey, events: &mut Vec<BattleEvent>) {
+        events.push(BattleEvent::Fainted {
+            player_id: key.0,
+            sprite_index: key.1,
+        });
+        self.status_effects.clear(key);
+        self.stat_stages.reset(key);
+    }
+
+    /// 按概率触发技能的特殊效果
+    fn apply_special_effect(
+        &mut self,
+        player_id: u64,
+        special_effect: SkillSpecialEffect,
+        events: &mut Vec<BattleEvent>,
+    ) {
+        let (chance, target) = match special_effect {
+            SkillSpecialEffect::BoostAttribute { chance, target, .. }
+            | SkillSpecialEffect::ReduceAttribute { chance, target, .. }
+            | SkillSpecialEffect::StatusEffect { chance, target, .. } => (chance, target),
+        };
+        let target_player_id = match target {
+            EffectTarget::User => player_id,
+            EffectTarget::Opponent => self.opponent_of(player_id),
+        };
+        // 倒下的精灵不受特殊效果影响
+        let Some(key) = self.current_key(target_player_id).filter(|_| {
+            self.current_sprite(target_player_id)
+                .is_some_and(|sprite| sprite.hp > 0)
+        }) else {
+            return;
+        };
+        if !roll_chance(&mut self.rng, chance) {
+            return;
+        }
+        match special_effect {
+            SkillSpecialEffect::BoostAttribute { stat, stages, .. } => {
+                self.change_stat(key, stat, stages.min(MAX_STAGE as u8) as i8, events);
+            }
+            SkillSpecialEffect::ReduceAttribute { stat, stages, .. } => {
+                self.change_stat(key, stat, -(stages.min(MAX_STAGE as u8) as i8), events);
+            }
+            SkillSpecialEffect::StatusEffect {
+                effect, duration, ..
+            } => {
+                if let Some(sprite) = self
+                    .sprite_teams
+                    .get(&target_player_id)
+                    .and_then(|team| team.get(key.1))
+                {
+                    self.status_effects
+                        .apply(key, sprite, effect, duration, events);
+                }
+            }
+        }
+    }
+
+    /// 改变精灵的能力等级, 等级实际发生变化时记录事件
+    fn change_stat(
+        &mut self,
+        key: SpriteKey,
+        stat: BattleStat,
+        delta: i8,
+        events: &mut Vec<BattleEvent>,
+    ) {
+        let stages = self.stat_stages.change(key, stat, delta);
+        if stages != 0 {
+            events.push(BattleEvent::StatChanged {
+                player_id: key.0,
+                sprite_index: key.1,
+                stat,
+                stages,
+                current: self.stat_stages.get(key, stat),
+            });
+        }
+    }
+
+    /// 检查目标精灵是否可以上场
+    fn check_switch_target(&self, player_id: u64, sprite_index: usize) -> anyhow::Result<()> {
         let Some(target_sprite) = self
             .sprite_teams
             .get(&player_id)
-            .map(|team| &team[sprite_index])
+            .and_then(|team| team.get(sprite_index))
         else {
             return Err(anyhow::anyhow!("目标精灵不存在"));
         };
-        if target_sprite.hp <= 0 {
+        // 检查目标精灵的HP值是否大于0
+        if target_sprite.hp == 0 {
             return Err(anyhow::anyhow!("目标精灵生命值小于等于0"));
         }
+        // 不能切换为已经上场的精灵
+        if self.current_sprite_players.get(&player_id) == Some(&sprite_index) {
+            return Err(anyhow::anyhow!("目标精灵已经上场"));
+        }
+        Ok(())
+    }
+
+    /// 替换倒下的精灵, 双方都替换完成后进入下一回合
+    fn replace_sprite(
+        &mut self,
+        player_id: u64,
+        sprite_index: usize,
+    ) -> Result<(), RoomActionError> {
+        let PKState::WaitingReplacement { players, .. } = &self.pk_state else {
+            return Err(RoomActionError::WrongPhase);
+        };
+        if !players.contains(&player_id) {
+            return Err(RoomActionError::WrongPhase);
+        }
+        self.switch_current_sprite(player_id, sprite_index)
+            .map_err(|_| RoomActionError::InvalidSprite)?;
+        self.record_switch(player_id, sprite_index);
+        self.pk_state
+            .finish_replacement(player_id, self.rules.turn_timeout());
+        Ok(())
+    }
+
+    /// 切换精灵不占用回合时, 在回合结算之外立即切换上场的精灵
+    fn switch_out_of_turn(&mut self, player_id: u64, sprite_index: usize) -> anyhow::Result<()> {
+        self.switch_current_sprite(player_id, sprite_index)?;
+        // 已经提交的技能是按下场的精灵检查的, 需要为新上场的精灵重新提交
+        self.room_actions.remove(&player_id);
+        self.record_switch(player_id, sprite_index);
+        Ok(())
+    }
+
+    fn record_switch(&mut self, player_id: u64, sprite_index: usize) {
+        self.replay.inputs.push(ReplayInput::Switch {
+            player_id,
+            sprite_index,
+            timestamp: Utc::now().timestamp_millis(),
+        });
+    }
+
+    /// 替换超时, 为还没有替换的玩家选择队伍中第一只可以上场的精灵
+    pub fn auto_replace(&mut self) -> Vec<(u64, usize)> {
+        let PKState::WaitingReplacement { players, .. } = &self.pk_state else {
+            return vec![];
+        };
+        let mut replaced = vec![];
+        for player_id in players.clone() {
+            let sprite_index = self
+                .sprite_teams
+                .get(&player_id)
+                .and_then(|team| team.iter().position(|sprite| sprite.hp > 0));
+            if let Some(sprite_index) = sprite_index
+                && self.replace_sprite(player_id, sprite_index).is_ok()
+            {
+                replaced.push((player_id, sprite_index));
+            }
+        }
+        replaced
+    }
+
+    fn switch_current_sprite(&mut self, player_id: u64, sprite_index: usize) -> anyhow::Result<()> {
+        self.check_switch_target(player_id, sprite_index)?;
+        // 下场精灵的能力等级重置
+        if let Some(key) = self.current_key(player_id) {
+            self.stat_stages.reset(key);
+        }
         // 切换上场的精灵
         self.current_sprite_players.insert(player_id, sprite_index);
         Ok(())
     }
 }
 
+/// 按百分比概率判定是否触发
+fn roll_chance(rng: &mut impl Rng, chance: u8) -> bool {
+    chance >= 100 || rng.random_ratio(chance as u32, 100)
+}
+
+/// 将 tokio 的截止时间转换为 Unix 毫秒时间戳, 发送给客户端
+fn unix_millis(deadline: Instant) -> i64 {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    Utc::now().timestamp_millis() + remaining.as_millis() as i64
+}
+
+/// 行为优先级, 逃跑 > 切换精灵 > 使用道具 > 捕捉 > 技能攻击
+fn action_priority(action: &RoomAction) -> u8 {
+    match action {
+        RoomAction::Escape => 4,
+        RoomAction::SwitchSprite { .. } => 3,
+        RoomAction::UseItem { .. } => 2,
+        RoomAction::CatchSprite => 1,
+        RoomAction::SkillAttack { .. } => 0,
+    }
+}
+
 #[derive(Debug, Default)]
 pub enum PKState {
     /// 战斗开始
@@ -332,6 +1392,12 @@ pub enum PKState {
     WaitingSpriteTeams { deadline: Instant },
     /// 等待玩家释放技能
     WaitingSkillAttack { turn_deadline: Instant },
+    /// 上场精灵倒下, 等待玩家替换精灵
+    WaitingReplacement {
+        /// 还没有替换精灵的玩家
+        players: Vec<u64>,
+        deadline: Instant,
+    },
 
     /// 战斗结束
     Ended,
@@ -339,19 +1405,19 @@ pub enum PKState {
 
 impl PKState {
     /// 从战斗开始状态开始等待玩家队伍数据
-    fn start_waiting_teams(&mut self) {
+    fn start_waiting_teams(&mut self, timeout: Duration) {
         if let PKState::Start = self {
             *self = PKState::WaitingSpriteTeams {
-                deadline: Instant::now() + std::time::Duration::from_secs(60),
+                deadline: Instant::now() + timeout,
             };
         }
     }
 
     /// 从等待精灵队伍状态进入等待技能释放状态
-    fn start_waiting_skill(&mut self) {
+    fn start_waiting_skill(&mut self, timeout: Duration) {
         if matches!(self, PKState::WaitingSpriteTeams { .. }) {
             *self = PKState::WaitingSkillAttack {
-                turn_deadline: Instant::now() + std::time::Duration::from_secs(10),
+                turn_deadline: Instant::now() + timeout,
             };
         }
     }
@@ -361,21 +1427,1065 @@ impl PKState {
         match self {
             PKState::WaitingSpriteTeams { deadline } => Instant::now() > *deadline,
             PKState::WaitingSkillAttack { turn_deadline } => Instant::now() > *turn_deadline,
+            PKState::WaitingReplacement { deadline, .. } => Instant::now() > *deadline,
             _ => false,
         }
     }
 
     /// 进入下一个回合
-    fn next_turn(&mut self) {
+    fn next_turn(&mut self, timeout: Duration) {
         if matches!(self, PKState::WaitingSkillAttack { .. }) {
             *self = PKState::WaitingSkillAttack {
-                turn_deadline: Instant::now() + std::time::Duration::from_secs(10),
+                turn_deadline: Instant::now() + timeout,
             };
         }
     }
 
+    /// 回合结算后有精灵倒下, 等待玩家替换精灵
+    fn start_waiting_replacement(&mut self, players: Vec<u64>, timeout: Duration) {
+        if matches!(self, PKState::WaitingSkillAttack { .. }) {
+            *self = PKState::WaitingReplacement {
+                players,
+                deadline: Instant::now() + timeout,
+            };
+        }
+    }
+
+    /// 玩家完成替换, 所有玩家都替换完成后进入下一个回合
+    fn finish_replacement(&mut self, player_id: u64, turn_timeout: Duration) {
+        if let PKState::WaitingReplacement { players, .. } = self {
+            players.retain(|id| *id != player_id);
+            if players.is_empty() {
+                *self = PKState::WaitingSkillAttack {
+                    turn_deadline: Instant::now() + turn_timeout,
+                };
+            }
+        }
+    }
+
     /// 结束战斗
     fn end_battle(&mut self) {
         *self = PKState::Ended;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use common::{
+        buff_effect::ExceptionEffect,
+        sprites::{
+            attributes::{Attribute, SkillType},
+            skills::Skill,
+        },
+    };
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        fixtures,
+        session::{OUTBOUND_CAPACITY, Outbound},
+    };
+
+    fn sprite(id: u64, speed: u16, skills: Vec<Skill>) -> Sprite {
+        Sprite {
+            speed,
+            skills,
+            ..fixtures::sprite(id)
+        }
+    }
+
+    fn skill(id: u64, power: u16, priority: i8) -> Skill {
+        Skill {
+            id,
+            name: format!("技能{}", id),
+            description: String::new(),
+            skill_type: SkillType::Physical,
+            attribute: Attribute::Huo,
+            pp: 10,
+            max_pp: 10,
+            power,
+            accuracy: 100,
+            priority,
+            special_effect: None,
+        }
+    }
+
+    /// 玩家1的精灵速度更快, 玩家2的精灵更慢
+    fn battle_state() -> GameState {
+        let mut game_state = GameState::new([1, 2], BattleRules::default(), 0);
+        let team_a = vec![
+            sprite(1, 200, vec![skill(1, 100, 0)]),
+            sprite(2, 100, vec![skill(1, 100, 0)]),
+        ];
+        let team_b = vec![
+            sprite(3, 100, vec![skill(1, 1000, 0), skill(2, 1000, 1)]),
+            sprite(4, 100, vec![skill(1, 100, 0)]),
+        ];
+        game_state.sprite_teams.insert(1, team_a);
+        game_state.sprite_teams.insert(2, team_b);
+        game_state.current_sprite_players.insert(1, 0);
+        game_state.current_sprite_players.insert(2, 0);
+        game_state
+    }
+
+    #[test]
+    fn test_resolve_turn_faster_sprite_attacks_first() {
+        let mut game_state = battle_state();
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        game_state
+            .room_actions
+            .insert(2, RoomAction::SkillAttack { skill_id: 1 });
+        game_state.resolve_turn();
+
+        assert!(game_state.room_actions.is_empty());
+        let hp = game_state.current_sprite(2).unwrap().hp;
+        assert!(hp > 0 && hp < 400);
+        assert_eq!(game_state.current_sprite(1).unwrap().hp, 0);
+    }
+
+    #[test]
+    fn test_resolve_turn_preemptive_skill_attacks_first() {
+        let mut game_state = battle_state();
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        game_state
+            .room_actions
+            .insert(2, RoomAction::SkillAttack { skill_id: 2 });
+        game_state.resolve_turn();
+
+        // 玩家2的先手技能击倒了玩家1的精灵, 玩家1的精灵无法出手
+        assert_eq!(game_state.current_sprite(1).unwrap().hp, 0);
+        assert_eq!(game_state.current_sprite(2).unwrap().hp, 400);
+    }
+
+    #[test]
+    fn test_resolve_turn_records_events_in_order() {
+        let mut game_state = battle_state();
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        game_state
+            .room_actions
+            .insert(2, RoomAction::SkillAttack { skill_id: 2 });
+        let events = game_state.resolve_turn();
+
+        assert_eq!(game_state.turn, 2);
+        assert_eq!(
+            events[0],
+            BattleEvent::SkillUsed {
+                player_id: 2,
+                skill_id: 2
+            }
+        );
+        assert!(matches!(
+            events[1],
+            BattleEvent::Damage {
+                player_id: 1,
+                sprite_index: 0,
+                remaining_hp: 0,
+                ..
+            }
+        ));
+        assert_eq!(
+            events[2],
+            BattleEvent::Fainted {
+                player_id: 1,
+                sprite_index: 0
+            }
+        );
+        // 倒下的精灵没有出手
+        assert_eq!(events.len(), 3);
+        assert!(game_state.needs_replacement(1));
+    }
+
+    #[test]
+    fn test_controlled_sprite_cannot_act() {
+        let mut game_state = battle_state();
+        let stunned = game_state.sprite_teams[&2][0].clone();
+        let mut events = vec![];
+        game_state
+            .status_effects
+            .apply((2, 0), &stunned, ExceptionEffect::Stun, 1, &mut events);
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        game_state
+            .room_actions
+            .insert(2, RoomAction::SkillAttack { skill_id: 2 });
+        let events = game_state.resolve_turn();
+
+        assert!(events.contains(&BattleEvent::CannotAct {
+            player_id: 2,
+            effect: ExceptionEffect::Stun
+        }));
+        assert_eq!(game_state.current_sprite(1).unwrap().hp, 400);
+        // 异常状态跟随精灵, 切换下场后仍然保留
+        game_state.switch_current_sprite(2, 1).unwrap();
+        assert!(game_state.status_effects.has((2, 0), ExceptionEffect::Stun));
+        assert_eq!(game_state.current_key(2), Some((2, 1)));
+    }
+
+    #[test]
+    fn test_actions_ready_when_opponent_cannot_act() {
+        let mut game_state = battle_state();
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        assert!(!game_state.is_room_actions_ready(2));
+
+        // 对方被冰冻
+        let frozen = game_state.sprite_teams[&2][0].clone();
+        game_state
+            .status_effects
+            .apply((2, 0), &frozen, ExceptionEffect::Freeze, 1, &mut vec![]);
+        assert!(!game_state.can_act(2));
+        assert!(game_state.is_room_actions_ready(2));
+        game_state.status_effects.clear((2, 0));
+
+        // 对方所有技能的PP耗尽
+        for skill in &mut game_state.sprite_teams.get_mut(&2).unwrap()[0].skills {
+            skill.pp = 0;
+        }
+        assert!(game_state.is_room_actions_ready(2));
+
+        // 对方的精灵已经倒下
+        let mut game_state = battle_state();
+        game_state.sprite_teams.get_mut(&2).unwrap()[0].hp = 0;
+        assert!(!game_state.is_room_actions_ready(2));
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        assert!(game_state.is_room_actions_ready(2));
+    }
+
+    #[test]
+    fn test_skill_special_effects_and_stat_stages() {
+        let mut game_state = battle_state();
+        let team_a = game_state.sprite_teams.get_mut(&1).unwrap();
+        team_a[0].skills = vec![Skill {
+            power: 0,
+            special_effect: Some(SkillSpecialEffect::BoostAttribute {
+                stat: BattleStat::Speed,
+                stages: 2,
+                chance: 100,
+                target: EffectTarget::User,
+            }),
+            ..skill(1, 0, 0)
+        }];
+        let team_b = game_state.sprite_teams.get_mut(&2).unwrap();
+        team_b[0].skills = vec![Skill {
+            power: 0,
+            special_effect: Some(SkillSpecialEffect::StatusEffect {
+                effect: ExceptionEffect::Poisoning,
+                duration: 2,
+                chance: 100,
+                target: EffectTarget::Opponent,
+            }),
+            ..skill(1, 0, 0)
+        }];
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        game_state
+            .room_actions
+            .insert(2, RoomAction::SkillAttack { skill_id: 1 });
+        let events = game_state.resolve_turn();
+
+        // 变化技能不造成伤害
+        assert!(
+            !events
+                .iter()
+                .any(|event| matches!(event, BattleEvent::Damage { .. }))
+        );
+        assert!(events.contains(&BattleEvent::StatChanged {
+            player_id: 1,
+            sprite_index: 0,
+            stat: BattleStat::Speed,
+            stages: 2,
+            current: 2,
+        }));
+        assert_eq!(game_state.effective_sprite(1).unwrap().speed, 400);
+        assert!(
+            game_state
+                .status_effects
+                .has((1, 0), ExceptionEffect::Poisoning)
+        );
+
+        // 下场后能力等级重置
+        game_state.switch_current_sprite(1, 1).unwrap();
+        game_state.switch_current_sprite(1, 0).unwrap();
+        assert_eq!(game_state.stat_stages.get((1, 0), BattleStat::Speed), 0);
+        assert!(
+            game_state
+                .status_effects
+                .has((1, 0), ExceptionEffect::Poisoning)
+        );
+    }
+
+    #[test]
+    fn test_faint_from_status_damage() {
+        let mut game_state = battle_state();
+        let key = (1, 0);
+        let sprite = &mut game_state.sprite_teams.get_mut(&1).unwrap()[0];
+        sprite.hp = 1;
+        game_state.stat_stages.change(key, BattleStat::Speed, 2);
+        game_state.status_effects.apply(
+            key,
+            sprite,
+            ExceptionEffect::Poisoning,
+            3,
+            &mut Vec::new(),
+        );
+
+        // 施加的回合不结算, 下一回合因中毒倒下
+        game_state.resolve_turn();
+        let events = game_state.resolve_turn();
+        assert!(events.contains(&BattleEvent::Fainted {
+            player_id: 1,
+            sprite_index: 0,
+        }));
+        // 与被技能击倒一样清除异常状态与能力等级
+        assert!(game_state.status_effects.effects(key).is_empty());
+        assert_eq!(game_state.stat_stages.get(key, BattleStat::Speed), 0);
+    }
+
+    /// 启动房间并提交 [`battle_state`] 中双方的精灵队伍, 返回房间的信箱与双方连接的发件箱
+    async fn start_room() -> (
+        mpsc::Sender<RoomActorMessage>,
+        HashMap<u64, mpsc::Receiver<Outbound>>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        start_room_with_rules(BattleRules::default()).await
+    }
+
+    async fn start_room_with_rules(
+        rules: BattleRules,
+    ) -> (
+        mpsc::Sender<RoomActorMessage>,
+        HashMap<u64, mpsc::Receiver<Outbound>>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let (room_sender, outbound_receivers, room) = spawn_room(rules).await;
+        let mut game_state = battle_state();
+        for player_id in [1, 2] {
+            room_sender
+                .send(RoomActorMessage::SpriteTeam {
+                    player_id,
+                    sprite_team: game_state.sprite_teams.remove(&player_id).unwrap(),
+                })
+                .await
+                .unwrap();
+        }
+        (room_sender, outbound_receivers, room)
+    }
+
+    /// 注册双方的会话并启动房间, 不提交精灵队伍
+    async fn spawn_room(
+        rules: BattleRules,
+    ) -> (
+        mpsc::Sender<RoomActorMessage>,
+        HashMap<u64, mpsc::Receiver<Outbound>>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let session_manager = SessionManager::new();
+        let mut outbound_receivers = HashMap::new();
+        for player_id in [1, 2] {
+            let (actor_sender, _actor_receiver) = mpsc::channel(8);
+            let (outbound_sender, outbound_receiver) = mpsc::channel(OUTBOUND_CAPACITY);
+            session_manager
+                .register(player_id, actor_sender, outbound_sender)
+                .await;
+            outbound_receivers.insert(player_id, outbound_receiver);
+        }
+        let (room_sender, room_receiver) = mpsc::channel(8);
+        let mut room_actor = RoomActor::new(
+            1,
+            EventBus::new(),
+            [1, 2],
+            room_receiver,
+            session_manager,
+            rules,
+            0,
+        );
+        let room = tokio::spawn(async move { room_actor.run().await });
+        (room_sender, outbound_receivers, room)
+    }
+
+    /// 接收下一条发给玩家的消息
+    async fn next_payload(receiver: &mut mpsc::Receiver<Outbound>) -> ServerPayload {
+        match tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await {
+            Ok(Some(Outbound::Payload(payload))) => payload,
+            other => panic!("没有收到消息: {:?}", other),
+        }
+    }
+
+    async fn submit(
+        room_sender: &mpsc::Sender<RoomActorMessage>,
+        player_id: u64,
+        action: RoomAction,
+    ) {
+        room_sender
+            .send(RoomActorMessage::RoomAction(RoomActionEnvelope {
+                player_id,
+                action,
+            }))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_room_pushes_battle_start_and_end() {
+        let (room_sender, mut outbound_receivers, room) = start_room().await;
+        let ServerPayload::BattleStart {
+            opponent_id,
+            opponent_team,
+            ..
+        } = next_payload(outbound_receivers.get_mut(&1).unwrap()).await
+        else {
+            panic!("玩家1应该收到对战开始消息");
+        };
+        assert_eq!(opponent_id, 2);
+        assert_eq!(
+            opponent_team
+                .iter()
+                .map(|sprite| sprite.id)
+                .collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+
+        submit(&room_sender, 1, RoomAction::Escape).await;
+        room.await.unwrap();
+
+        let receiver = outbound_receivers.get_mut(&2).unwrap();
+        let mut last_payload = None;
+        while let Ok(Outbound::Payload(payload)) = receiver.try_recv() {
+            last_payload = Some(payload);
+        }
+        assert!(matches!(
+            last_payload,
+            Some(ServerPayload::BattleEnd {
+                winner: Some(2),
+                reason: BattleEndReason::Escape
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_forfeit_when_team_not_submitted() {
+        let (room_sender, mut outbound_receivers, room) = spawn_room(BattleRules {
+            team_timeout_secs: 1,
+            tick_millis: 100,
+            ..Default::default()
+        })
+        .await;
+
+        // 只有玩家1提交了精灵队伍
+        let mut game_state = battle_state();
+        room_sender
+            .send(RoomActorMessage::SpriteTeam {
+                player_id: 1,
+                sprite_team: game_state.sprite_teams.remove(&1).unwrap(),
+            })
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(3), room)
+            .await
+            .expect("等待队伍超时后房间应该关闭")
+            .unwrap();
+
+        for player_id in [1, 2] {
+            assert!(matches!(
+                next_payload(outbound_receivers.get_mut(&player_id).unwrap()).await,
+                ServerPayload::BattleEnd {
+                    winner: Some(1),
+                    reason: BattleEndReason::Forfeit
+                }
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_actions_before_battle_start() {
+        let (room_sender, mut outbound_receivers, _room) = spawn_room(BattleRules::default()).await;
+        let mut game_state = battle_state();
+        room_sender
+            .send(RoomActorMessage::SpriteTeam {
+                player_id: 1,
+                sprite_team: game_state.sprite_teams.remove(&1).unwrap(),
+            })
+            .await
+            .unwrap();
+
+        // 对方还没有提交队伍, 不能提交行为
+        let receiver = outbound_receivers.get_mut(&1).unwrap();
+        submit(&room_sender, 1, RoomAction::SkillAttack { skill_id: 1 }).await;
+        assert!(matches!(
+            next_payload(receiver).await,
+            ServerPayload::RoomActionRejected {
+                action: RoomAction::SkillAttack { skill_id: 1 },
+                reason: RoomActionError::WrongPhase,
+            }
+        ));
+
+        // 对方提交队伍后战斗从第一回合开始
+        room_sender
+            .send(RoomActorMessage::SpriteTeam {
+                player_id: 2,
+                sprite_team: game_state.sprite_teams.remove(&2).unwrap(),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_payload(receiver).await,
+            ServerPayload::BattleStart { .. }
+        ));
+        assert!(matches!(
+            next_payload(receiver).await,
+            ServerPayload::TurnStart { turn: 1, .. }
+        ));
+
+        // 战斗开始后重新提交的队伍被忽略, 上场的精灵不变
+        room_sender
+            .send(RoomActorMessage::SpriteTeam {
+                player_id: 1,
+                sprite_team: vec![sprite(9, 100, vec![])],
+            })
+            .await
+            .unwrap();
+        submit(&room_sender, 1, RoomAction::SkillAttack { skill_id: 1 }).await;
+        submit(&room_sender, 2, RoomAction::SkillAttack { skill_id: 1 }).await;
+        loop {
+            if let ServerPayload::TurnResult { turn, events } = next_payload(receiver).await {
+                assert_eq!(turn, 1);
+                assert!(events.contains(&BattleEvent::SkillUsed {
+                    player_id: 1,
+                    skill_id: 1
+                }));
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_switch_out_of_turn() {
+        let mut game_state = battle_state();
+        game_state.submit_action(1, RoomAction::SkillAttack { skill_id: 1 });
+        game_state.switch_out_of_turn(1, 1).unwrap();
+        assert_eq!(game_state.current_sprite_players[&1], 1);
+        // 切换前提交的技能被清除
+        assert!(!game_state.room_actions.contains_key(&1));
+        // 不能切换为已经上场的精灵
+        assert!(game_state.switch_out_of_turn(1, 1).is_err());
+        assert!(game_state.check_switch_target(1, 1).is_err());
+    }
+
+    #[test]
+    fn test_speed_tie_is_random() {
+        let first_movers: std::collections::HashSet<u64> = (0..20)
+            .map(|seed| {
+                let mut game_state = battle_state();
+                game_state.rng = StdRng::seed_from_u64(seed);
+                game_state.sprite_teams.get_mut(&1).unwrap()[0].speed = 100;
+                game_state
+                    .room_actions
+                    .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+                game_state
+                    .room_actions
+                    .insert(2, RoomAction::SkillAttack { skill_id: 1 });
+                let events = game_state.resolve_turn();
+                events
+                    .iter()
+                    .find_map(|event| match event {
+                        BattleEvent::SkillUsed { player_id, .. } => Some(*player_id),
+                        _ => None,
+                    })
+                    .unwrap()
+            })
+            .collect();
+        // 速度相同时双方都有机会先出手
+        assert_eq!(first_movers, std::collections::HashSet::from([1, 2]));
+    }
+
+    #[test]
+    fn test_resolve_turn_switch_before_skill_attack() {
+        let mut game_state = battle_state();
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SwitchSprite { sprite_index: 1 });
+        game_state
+            .room_actions
+            .insert(2, RoomAction::SkillAttack { skill_id: 1 });
+        game_state.resolve_turn();
+
+        // 切换后上场的精灵承受了攻击
+        assert_eq!(game_state.current_sprite_players[&1], 1);
+        assert_eq!(game_state.sprite_teams[&1][0].hp, 400);
+        assert_eq!(game_state.sprite_teams[&1][1].hp, 0);
+    }
+
+    #[test]
+    fn test_check_skill_attack_and_consume_pp() {
+        let mut game_state = battle_state();
+        assert_eq!(
+            game_state.check_skill_attack(1, 99),
+            Err(RoomActionError::UnknownSkill)
+        );
+        game_state.sprite_teams.get_mut(&1).unwrap()[0].skills[0].pp = 1;
+        assert_eq!(game_state.check_skill_attack(1, 1), Ok(()));
+
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        game_state
+            .room_actions
+            .insert(2, RoomAction::SwitchSprite { sprite_index: 1 });
+        game_state.resolve_turn();
+        assert_eq!(game_state.current_sprite(1).unwrap().skills[0].pp, 0);
+        assert_eq!(
+            game_state.check_skill_attack(1, 1),
+            Err(RoomActionError::NoPP)
+        );
+
+        let stunned = game_state.sprite_teams[&2][1].clone();
+        game_state
+            .status_effects
+            .apply((2, 1), &stunned, ExceptionEffect::Stun, 1, &mut vec![]);
+        assert_eq!(
+            game_state.check_skill_attack(2, 1),
+            Err(RoomActionError::CannotAct)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resubmit_after_rejected_skill() {
+        let (room_sender, mut outbound_receivers, _room) = start_room().await;
+        let receiver = outbound_receivers.get_mut(&1).unwrap();
+        assert!(matches!(
+            next_payload(receiver).await,
+            ServerPayload::BattleStart { .. }
+        ));
+        assert!(matches!(
+            next_payload(receiver).await,
+            ServerPayload::TurnStart { turn: 1, .. }
+        ));
+
+        submit(&room_sender, 1, RoomAction::SkillAttack { skill_id: 99 }).await;
+        assert!(matches!(
+            next_payload(receiver).await,
+            ServerPayload::RoomActionRejected {
+                action: RoomAction::SkillAttack { skill_id: 99 },
+                reason: RoomActionError::UnknownSkill,
+            }
+        ));
+
+        // 被拒绝后重新提交, 双方提交完成后结算回合
+        submit(&room_sender, 1, RoomAction::SkillAttack { skill_id: 1 }).await;
+        submit(&room_sender, 2, RoomAction::SkillAttack { skill_id: 1 }).await;
+        loop {
+            if let ServerPayload::TurnResult { turn, events } = next_payload(receiver).await {
+                assert_eq!(turn, 1);
+                assert!(events.contains(&BattleEvent::SkillUsed {
+                    player_id: 1,
+                    skill_id: 1
+                }));
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_action_on_turn_timeout() {
+        let mut game_state = battle_state();
+        game_state.rules.max_afk_turns = 2;
+        game_state
+            .pk_state
+            .start_waiting_teams(Duration::from_secs(60));
+        game_state
+            .pk_state
+            .start_waiting_skill(Duration::from_secs(10));
+
+        // 玩家1提交的行为照常结算, 玩家2使用第一个还有PP的技能
+        game_state.submit_action(1, RoomAction::SkillAttack { skill_id: 1 });
+        let skills = &mut game_state.sprite_teams.get_mut(&2).unwrap()[0].skills;
+        skills[0].pp = 0;
+        skills[1].power = 10;
+        game_state.fill_timeout_actions();
+        assert!(matches!(
+            game_state.room_actions.get(&2),
+            Some(RoomAction::SkillAttack { skill_id: 2 })
+        ));
+        let events = game_state.resolve_turn();
+        assert!(events.contains(&BattleEvent::SkillUsed {
+            player_id: 1,
+            skill_id: 1
+        }));
+        assert!(events.contains(&BattleEvent::SkillUsed {
+            player_id: 2,
+            skill_id: 2
+        }));
+
+        // 主动提交后连续超时的回合数重置
+        game_state.submit_action(2, RoomAction::SkillAttack { skill_id: 2 });
+        assert!(!game_state.afk_turns.contains_key(&2));
+        game_state.room_actions.clear();
+
+        // 连续超时达到上限时判负
+        game_state.afk_turns.insert(2, 1);
+        game_state.fill_timeout_actions();
+        assert_eq!(
+            game_state
+                .result
+                .map(|result| (result.winner, result.reason)),
+            Some((Some(1), BattleEndReason::Forfeit))
+        );
+    }
+
+    #[test]
+    fn test_struggle_when_all_pp_exhausted() {
+        let mut game_state = battle_state();
+        for skill in &mut game_state.sprite_teams.get_mut(&2).unwrap()[0].skills {
+            skill.pp = 0;
+        }
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        let events = game_state.resolve_turn();
+
+        assert!(events.contains(&BattleEvent::SkillUsed {
+            player_id: 2,
+            skill_id: STRUGGLE_SKILL_ID
+        }));
+        // 挣扎的反伤为最大HP的四分之一
+        assert!(events.iter().any(|event| matches!(
+            event,
+            BattleEvent::Damage {
+                player_id: 2,
+                damage: 100,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn test_submit_team_applies_rules() {
+        let mut game_state = GameState::new(
+            [1, 2],
+            BattleRules {
+                team_size: 2,
+                level_cap: 50,
+                ..Default::default()
+            },
+            0,
+        );
+        let mut team = vec![sprite(1, 100, vec![]), sprite(2, 100, vec![])];
+        team.extend((3..=5).map(|id| Sprite {
+            level: 50,
+            ..sprite(id, 100, vec![])
+        }));
+        assert!(game_state.submit_team(1, team));
+        assert_eq!(
+            game_state.sprite_teams[&1]
+                .iter()
+                .map(|sprite| sprite.id)
+                .collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+        // 没有符合等级上限的精灵
+        assert!(!game_state.submit_team(2, vec![sprite(6, 100, vec![])]));
+        assert!(!game_state.is_teams_ready());
+    }
+
+    #[test]
+    fn test_turn_limit_tie_break() {
+        let mut game_state = battle_state();
+        game_state.rules.max_turns = 1;
+        game_state.rules.tie_break = TieBreak::RemainingSprites;
+        game_state
+            .pk_state
+            .start_waiting_teams(Duration::from_secs(60));
+        game_state
+            .pk_state
+            .start_waiting_skill(Duration::from_secs(10));
+        game_state.sprite_teams.get_mut(&1).unwrap()[1].hp = 0;
+        game_state.resolve_turn();
+        assert_eq!(
+            game_state.result,
+            Some(BattleResult {
+                winner: Some(2),
+                reason: BattleEndReason::TurnLimit,
+                turns: 1,
+            })
+        );
+
+        let mut game_state = battle_state();
+        game_state.rules.max_turns = 1;
+        game_state.rules.tie_break = TieBreak::Draw;
+        game_state.sprite_teams.get_mut(&1).unwrap()[1].hp = 0;
+        game_state.resolve_turn();
+        assert_eq!(game_state.result.map(|result| result.winner), Some(None));
+    }
+
+    #[tokio::test]
+    async fn test_reject_items_when_not_allowed() {
+        let (room_sender, mut outbound_receivers, _room) = start_room_with_rules(BattleRules {
+            allow_items: false,
+            ..Default::default()
+        })
+        .await;
+        submit(&room_sender, 1, RoomAction::UseItem { item_id: 1 }).await;
+        let receiver = outbound_receivers.get_mut(&1).unwrap();
+        loop {
+            if let ServerPayload::RoomActionRejected { action, reason } =
+                next_payload(receiver).await
+            {
+                assert!(matches!(action, RoomAction::UseItem { item_id: 1 }));
+                assert_eq!(reason, RoomActionError::ItemsNotAllowed);
+                break;
+            }
+        }
+
+        // 玩家之间的对战不能捕捉精灵
+        submit(&room_sender, 1, RoomAction::CatchSprite).await;
+        assert!(matches!(
+            next_payload(receiver).await,
+            ServerPayload::RoomActionRejected {
+                action: RoomAction::CatchSprite,
+                reason: RoomActionError::CatchNotAllowed,
+            }
+        ));
+    }
+
+    #[test]
+    fn test_same_seed_same_battle() {
+        let play = |seed: u64| {
+            let mut game_state = battle_state();
+            game_state.seed = seed;
+            game_state.rng = StdRng::seed_from_u64(seed);
+            for sprite in game_state.sprite_teams.values_mut().flatten() {
+                for skill in &mut sprite.skills {
+                    skill.power = 50;
+                    skill.accuracy = 70;
+                }
+            }
+            let mut events = vec![];
+            for _ in 0..3 {
+                game_state
+                    .room_actions
+                    .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+                game_state
+                    .room_actions
+                    .insert(2, RoomAction::SkillAttack { skill_id: 1 });
+                events.extend(game_state.resolve_turn());
+            }
+            events
+        };
+        assert_eq!(play(42), play(42));
+    }
+
+    #[test]
+    fn test_skill_miss() {
+        let mut game_state = battle_state();
+        game_state.sprite_teams.get_mut(&1).unwrap()[0].skills[0].accuracy = 0;
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        let events = game_state.resolve_turn();
+        assert!(events.contains(&BattleEvent::Miss {
+            player_id: 1,
+            skill_id: 1
+        }));
+        // 未命中时PP照常消耗
+        assert_eq!(game_state.sprite_teams[&1][0].skills[0].pp, 9);
+        assert_eq!(game_state.sprite_teams[&2][0].hp, 400);
+    }
+
+    #[test]
+    fn test_replay_reproduces_battle() {
+        let mut teams = battle_state().sprite_teams;
+        let mut game_state = GameState::new([1, 2], BattleRules::default(), 7);
+        game_state
+            .pk_state
+            .start_waiting_teams(Duration::from_secs(60));
+        for player_id in [1, 2] {
+            game_state.submit_team(player_id, teams.remove(&player_id).unwrap());
+        }
+        game_state
+            .pk_state
+            .start_waiting_skill(Duration::from_secs(10));
+        while !game_state.is_end() {
+            if matches!(game_state.pk_state, PKState::WaitingReplacement { .. }) {
+                game_state.auto_replace();
+                continue;
+            }
+            for player_id in [1, 2] {
+                if let Some(action) = game_state.default_action(player_id) {
+                    game_state.submit_action(player_id, action);
+                }
+            }
+            game_state.resolve_turn();
+        }
+
+        let replay = BattleReplay::decode(&game_state.replay.encode().unwrap()).unwrap();
+        let simulated = replay.verify().unwrap();
+        assert_eq!(simulated.turn, game_state.turn);
+        for player_id in [1, 2] {
+            let hp = |state: &GameState| {
+                state.sprite_teams[&player_id]
+                    .iter()
+                    .map(|sprite| sprite.hp)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(hp(&simulated), hp(&game_state));
+        }
+
+        // 记录的结果与模拟结果不一致
+        let mut tampered = replay.clone();
+        tampered.result = Some(BattleResult {
+            winner: None,
+            reason: BattleEndReason::Timeout,
+            turns: 0,
+        });
+        assert!(tampered.verify().is_err());
+
+        // 更换随机数种子后模拟的战斗与记录不一致
+        let mut reseeded = replay.clone();
+        reseeded.seed += 1;
+        assert!(reseeded.verify().is_err());
+
+        // 修改一个回合的行为后模拟的战斗与记录不一致
+        let mut altered = replay;
+        let turn = altered
+            .inputs
+            .iter_mut()
+            .find_map(|input| match input {
+                ReplayInput::Turn { actions, .. } if !actions.is_empty() => Some(actions),
+                _ => None,
+            })
+            .unwrap();
+        turn[0].1 = RoomAction::SwitchSprite { sprite_index: 1 };
+        assert!(altered.verify().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_room_saves_replay() {
+        let replay_dir = std::env::temp_dir().join(format!("replays_{}", rand::random::<u64>()));
+        let session_manager = SessionManager::new();
+        let (room_sender, room_receiver) = mpsc::channel(8);
+        let mut room_actor = RoomActor::new(
+            1,
+            EventBus::new(),
+            [1, 2],
+            room_receiver,
+            session_manager,
+            BattleRules::default(),
+            42,
+        )
+        .with_replay_dir(Some(replay_dir.clone()));
+        let room = tokio::spawn(async move { room_actor.run().await });
+
+        let mut game_state = battle_state();
+        for player_id in [1, 2] {
+            room_sender
+                .send(RoomActorMessage::SpriteTeam {
+                    player_id,
+                    sprite_team: game_state.sprite_teams.remove(&player_id).unwrap(),
+                })
+                .await
+                .unwrap();
+        }
+        submit(&room_sender, 1, RoomAction::Escape).await;
+        room.await.unwrap();
+
+        let replay = replay::load_replay(&replay_dir.join("room_1_42.replay"))
+            .await
+            .unwrap();
+        assert_eq!(replay.seed, 42);
+        assert_eq!(replay.teams.len(), 2);
+        assert_eq!(
+            replay.result.map(|result| (result.winner, result.reason)),
+            Some((Some(2), BattleEndReason::Escape))
+        );
+        replay.verify().unwrap();
+        std::fs::remove_dir_all(replay_dir).unwrap();
+    }
+
+    #[test]
+    fn test_replace_fainted_sprite() {
+        let mut game_state = battle_state();
+        game_state
+            .pk_state
+            .start_waiting_teams(Duration::from_secs(60));
+        game_state
+            .pk_state
+            .start_waiting_skill(Duration::from_secs(10));
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        game_state
+            .room_actions
+            .insert(2, RoomAction::SkillAttack { skill_id: 2 });
+        game_state.resolve_turn();
+
+        let PKState::WaitingReplacement { players, .. } = &game_state.pk_state else {
+            panic!("应该等待替换精灵: {:?}", game_state.pk_state);
+        };
+        assert_eq!(players, &vec![1]);
+        assert_eq!(
+            game_state.replace_sprite(2, 1),
+            Err(RoomActionError::WrongPhase)
+        );
+        assert_eq!(
+            game_state.replace_sprite(1, 0),
+            Err(RoomActionError::InvalidSprite)
+        );
+        assert_eq!(game_state.replace_sprite(1, 1), Ok(()));
+        assert!(matches!(
+            game_state.pk_state,
+            PKState::WaitingSkillAttack { .. }
+        ));
+    }
+
+    #[test]
+    fn test_auto_replace_picks_first_alive_sprite() {
+        let mut game_state = battle_state();
+        game_state.sprite_teams.get_mut(&1).unwrap()[0].hp = 0;
+        game_state.pk_state = PKState::WaitingReplacement {
+            players: vec![1],
+            deadline: Instant::now(),
+        };
+        assert_eq!(game_state.auto_replace(), vec![(1, 1)]);
+        assert_eq!(game_state.current_key(1), Some((1, 1)));
+    }
+
+    #[test]
+    fn test_battle_ends_when_all_sprites_fainted() {
+        let mut game_state = battle_state();
+        game_state
+            .pk_state
+            .start_waiting_teams(Duration::from_secs(60));
+        game_state
+            .pk_state
+            .start_waiting_skill(Duration::from_secs(10));
+        game_state.sprite_teams.get_mut(&1).unwrap()[1].hp = 0;
+        game_state
+            .room_actions
+            .insert(1, RoomAction::SkillAttack { skill_id: 1 });
+        game_state
+            .room_actions
+            .insert(2, RoomAction::SkillAttack { skill_id: 2 });
+        game_state.resolve_turn();
+
+        assert!(game_state.is_end());
+        assert_eq!(
+            game_state.result,
+            Some(BattleResult {
+                winner: Some(2),
+                reason: BattleEndReason::AllFainted,
+                turns: 1,
+            })
+        );
+    }
+}
@@ -1,17 +1,20 @@
 use std::{
     collections::HashMap,
+    path::PathBuf,
     sync::{
         Arc,
         atomic::{AtomicU64, Ordering},
     },
 };
 
+use common::message::MatchMode;
 use tokio::sync::{
     RwLock,
     mpsc::{self, Sender},
 };
 
 use crate::{
+    battle_rules::get_battle_rules,
     events::EventBus,
     room::{RoomActor, RoomActorMessage},
     session::SessionManager,
@@ -20,25 +23,65 @@ use crate::{
 /// 全局唯一的房间ID生成器
 static NEXT_ROOM_ID: AtomicU64 = AtomicU64::new(1);
 
+/// 房间的元数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoomInfo {
+    pub players: [u64; 2],
+    pub mode: MatchMode,
+    /// 房间战斗使用的随机数种子, 用于复现战斗
+    pub seed: u64,
+}
+
+/// 房间的信箱与元数据
+#[derive(Debug)]
+struct RoomHandle {
+    sender: Sender<RoomActorMessage>,
+    info: RoomInfo,
+}
+
 #[derive(Debug, Clone)]
 pub struct RoomManager {
     /// 房间ID生成器
-    rooms: Arc<RwLock<HashMap<u64, Sender<RoomActorMessage>>>>,
+    rooms: Arc<RwLock<HashMap<u64, RoomHandle>>>,
+    /// 战斗结束时保存回放的目录, 为空时不保存
+    replay_dir: Option<PathBuf>,
+}
+
+impl Default for RoomManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RoomManager {
     pub fn new() -> Self {
         Self {
             rooms: Default::default(),
+            replay_dir: None,
         }
     }
 
+    /// 设置战斗结束时保存回放的目录
+    pub fn with_replay_dir(mut self, replay_dir: impl Into<PathBuf>) -> Self {
+        self.replay_dir = Some(replay_dir.into());
+        self
+    }
+
     pub async fn get_latest_room_id(&self) -> u64 {
         NEXT_ROOM_ID.load(Ordering::SeqCst) - 1
     }
 
     pub async fn get_room_sender(&self, room_id: u64) -> Option<Sender<RoomActorMessage>> {
-        self.rooms.read().await.get(&room_id).cloned()
+        self.rooms
+            .read()
+            .await
+            .get(&room_id)
+            .map(|room| room.sender.clone())
+    }
+
+    /// 获取房间的元数据
+    pub async fn get_room_info(&self, room_id: u64) -> Option<RoomInfo> {
+        self.rooms.read().await.get(&room_id).map(|room| room.info)
     }
 
     /// 获取当前房间数量
@@ -49,27 +92,45 @@ impl RoomManager {
     pub async fn create_room(
         &self,
         players: [u64; 2],
+        mode: MatchMode,
         event_bus: EventBus,
         session_manager: SessionManager,
     ) -> u64 {
         let room_id = NEXT_ROOM_ID.fetch_add(1, Ordering::SeqCst);
+        let info = RoomInfo {
+            players,
+            mode,
+            seed: rand::random(),
+        };
         // 1. 创建 RoomActor
         let (sender, receiver) = mpsc::channel(128);
-        let mut room_actor = RoomActor::new(room_id, event_bus, players, receiver, session_manager);
+        let mut room_actor = RoomActor::new(
+            room_id,
+            event_bus,
+            players,
+            receiver,
+            session_manager,
+            get_battle_rules(mode),
+            info.seed,
+        )
+        .with_replay_dir(self.replay_dir.clone());
         // 2. 启动 RoomActor
         tokio::spawn(async move {
             room_actor.run().await;
         });
         // 3. 加入房间
-        self.rooms.write().await.insert(room_id, sender);
+        self.rooms
+            .write()
+            .await
+            .insert(room_id, RoomHandle { sender, info });
         room_id
     }
 
     pub async fn remove_room(&self, room_id: u64) {
         // 获取房间的sender并发送关闭消息
-        if let Some(sender) = self.rooms.write().await.remove(&room_id) {
+        if let Some(room) = self.rooms.write().await.remove(&room_id) {
             // 发送关闭消息给RoomActor
-            if let Err(e) = sender.send(RoomActorMessage::Close).await {
+            if let Err(e) = room.sender.send(RoomActorMessage::Close).await {
                 println!("发送关闭消息到房间 {} 失败: {:?}", room_id, e);
             }
             println!("房间 {} 已移除并发送关闭消息", room_id);
@@ -77,4 +138,11 @@ impl RoomManager {
             println!("房间 {} 不存在", room_id);
         }
     }
+
+    /// 移除已经结束的房间, 房间的Actor已经退出, 无需再发送关闭消息
+    pub async fn remove_finished_room(&self, room_id: u64) {
+        if self.rooms.write().await.remove(&room_id).is_some() {
+            println!("房间 {} 已结束并移除", room_id);
+        }
+    }
 }
@@ -1,12 +1,35 @@
 use std::{collections::HashMap, sync::Arc};
 
+use common::message::ServerPayload;
 use tokio::sync::{RwLock, mpsc};
 
 use crate::actor::ActorMessage;
 
+/// 玩家连接发件箱的容量, 客户端处理过慢导致发件箱已满时断开连接
+pub const OUTBOUND_CAPACITY: usize = 256;
+
+/// 发往玩家连接的消息, 由连接的写循环发送给客户端
+#[derive(Debug)]
+pub enum Outbound {
+    /// 发送给客户端的消息
+    Payload(ServerPayload),
+    /// 服务端主动断开连接
+    Disconnect,
+}
+
+/// 玩家会话的通信句柄
+struct SessionHandle {
+    /// 玩家 Actor 的信箱
+    actor: mpsc::Sender<ActorMessage>,
+    /// 玩家连接的发件箱, 容量为 [`OUTBOUND_CAPACITY`]
+    outbound: mpsc::Sender<Outbound>,
+}
+
 #[derive(Default, Clone)]
 pub struct SessionManager {
-    sessions: Arc<RwLock<HashMap<u64, mpsc::Sender<ActorMessage>>>>,
+    sessions: Arc<RwLock<HashMap<u64, SessionHandle>>>,
+    /// 玩家当前有效的 refresh token 的ID, 断开连接后仍然保留, 重新连接时使用
+    refresh_token_ids: Arc<RwLock<HashMap<u64, String>>>,
 }
 
 impl SessionManager {
@@ -15,9 +38,26 @@ impl SessionManager {
     }
 
     /// 注册会话
-    pub async fn register(&self, id: u64, tx: mpsc::Sender<ActorMessage>) {
+    ///
+    /// 玩家重复登录时断开之前的连接, 由新的连接接管会话
+    pub async fn register(
+        &self,
+        id: u64,
+        tx: mpsc::Sender<ActorMessage>,
+        outbound: mpsc::Sender<Outbound>,
+    ) {
         let mut sessions = self.sessions.write().await;
-        sessions.insert(id, tx);
+        let previous = sessions.insert(
+            id,
+            SessionHandle {
+                actor: tx,
+                outbound,
+            },
+        );
+        if let Some(previous) = previous {
+            println!("[SessionManager] 玩家 {} 重复登录, 断开之前的连接", id);
+            let _ = previous.outbound.try_send(Outbound::Disconnect);
+        }
         println!(
             "[SessionManager] 玩家 {} 连接，总共在线：{}",
             id,
@@ -25,8 +65,15 @@ impl SessionManager {
         );
     }
 
-    pub async fn unregister(&self, id: u64) {
+    /// 注销会话, 只注销属于该连接的会话, 玩家可能已经重新登录
+    pub async fn unregister(&self, id: u64, tx: &mpsc::Sender<ActorMessage>) {
         let mut sessions = self.sessions.write().await;
+        if !sessions
+            .get(&id)
+            .is_some_and(|session| session.actor.same_channel(tx))
+        {
+            return;
+        }
         sessions.remove(&id);
         println!(
             "[SessionManager] 玩家 {} 断开连接，总共在线：{}",
@@ -35,16 +82,135 @@ impl SessionManager {
         );
     }
 
+    /// 记录玩家新签发的 refresh token, 之前签发的 refresh token 随之失效
+    ///
+    /// 指定了 `used` 时, 只有它是当前有效的 refresh token 才会轮换, 返回是否轮换成功
+    pub async fn rotate_refresh_token(
+        &self,
+        player_id: u64,
+        used: Option<&str>,
+        refresh_token_id: String,
+    ) -> bool {
+        let mut refresh_token_ids = self.refresh_token_ids.write().await;
+        if let Some(used) = used
+            && refresh_token_ids.get(&player_id).map(String::as_str) != Some(used)
+        {
+            return false;
+        }
+        refresh_token_ids.insert(player_id, refresh_token_id);
+        true
+    }
+
     pub async fn get_session(&self, id: u64) -> Option<mpsc::Sender<ActorMessage>> {
         let sessions = self.sessions.read().await;
-        sessions.get(&id).cloned()
+        sessions.get(&id).map(|session| session.actor.clone())
     }
 
     pub async fn send_message(&self, id: u64, message: ActorMessage) {
         if let Some(session) = self.get_session(id).await {
-            session.send(message).await;
+            if let Err(e) = session.send(message).await {
+                println!("[SessionManager] 发送消息给玩家 {} 失败: {:?}", id, e);
+            }
         } else {
             println!("[SessionManager] 玩家 {} 不存在", id);
         }
     }
+
+    /// 发送消息给指定玩家的客户端
+    pub async fn send_to_player(&self, id: u64, payload: ServerPayload) {
+        self.send_outbound(id, Outbound::Payload(payload)).await;
+    }
+
+    /// 服务端主动断开指定玩家的连接
+    pub async fn disconnect_player(&self, id: u64) {
+        self.send_outbound(id, Outbound::Disconnect).await;
+    }
+
+    /// 不等待地放入玩家连接的发件箱, 发件箱已满时注销会话
+    ///
+    /// 会话注销后连接的写循环收完剩余的消息就会断开连接, 避免为处理过慢的客户端无限堆积消息
+    async fn send_outbound(&self, id: u64, outbound: Outbound) {
+        let sender = match self.sessions.read().await.get(&id) {
+            Some(session) => session.outbound.clone(),
+            None => {
+                println!("[SessionManager] 玩家 {} 不存在", id);
+                return;
+            }
+        };
+        match sender.try_send(outbound) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                let mut sessions = self.sessions.write().await;
+                // 只注销发件箱已满的会话, 玩家可能已经重新连接
+                if sessions
+                    .get(&id)
+                    .is_some_and(|session| session.outbound.same_channel(&sender))
+                {
+                    sessions.remove(&id);
+                    println!("[SessionManager] 玩家 {} 的发件箱已满, 断开连接", id);
+                }
+            }
+            Err(mpsc::error::TrySendError::Closed(outbound)) => {
+                println!(
+                    "[SessionManager] 发送消息给玩家 {} 失败: {:?}",
+                    id, outbound
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_disconnect_when_outbound_full() {
+        let session_manager = SessionManager::new();
+        let (actor_sender, _actor_receiver) = mpsc::channel(8);
+        let (outbound_sender, mut outbound_receiver) = mpsc::channel(OUTBOUND_CAPACITY);
+        session_manager
+            .register(1, actor_sender, outbound_sender)
+            .await;
+
+        for _ in 0..=OUTBOUND_CAPACITY {
+            session_manager.send_to_player(1, ServerPayload::Pong).await;
+        }
+        assert!(session_manager.get_session(1).await.is_none());
+
+        // 写循环收完已经放入发件箱的消息后断开连接
+        let mut received = 0;
+        while let Some(Outbound::Payload(_)) = outbound_receiver.recv().await {
+            received += 1;
+        }
+        assert_eq!(received, OUTBOUND_CAPACITY);
+    }
+
+    #[tokio::test]
+    async fn test_login_twice() {
+        let session_manager = SessionManager::new();
+        let (first_actor, _first_actor_receiver) = mpsc::channel(8);
+        let (first_outbound, mut first_outbound_receiver) = mpsc::channel(OUTBOUND_CAPACITY);
+        session_manager
+            .register(1, first_actor.clone(), first_outbound)
+            .await;
+
+        let (second_actor, _second_actor_receiver) = mpsc::channel(8);
+        let (second_outbound, _second_outbound_receiver) = mpsc::channel(OUTBOUND_CAPACITY);
+        session_manager
+            .register(1, second_actor.clone(), second_outbound)
+            .await;
+
+        // 之前的连接被断开, 断开时注销会话不影响新的连接
+        assert!(matches!(
+            first_outbound_receiver.recv().await,
+            Some(Outbound::Disconnect)
+        ));
+        session_manager.unregister(1, &first_actor).await;
+        let session = session_manager.get_session(1).await.unwrap();
+        assert!(session.same_channel(&second_actor));
+
+        session_manager.unregister(1, &second_actor).await;
+        assert!(session_manager.get_session(1).await.is_none());
+    }
 }
@@ -0,0 +1,403 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use common::{
+    message::{BattleEvent, RoomAction},
+    sprites::{
+        Sprite,
+        attributes::Attribute,
+        skills::{STRUGGLE_SKILL_ID, Skill},
+    },
+};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    battle_rules::BattleRules,
+    damage::defender_attributes,
+    room::{GameState, PKState},
+};
+
+/// 模拟对战中双方的玩家ID
+pub const PLAYERS: [u64; 2] = [1, 2];
+
+/// 模拟对战中玩家选择行为的策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// 使用第一个还有PP的技能
+    First,
+    /// 使用威力最大且还有PP的技能
+    Strongest,
+    /// 随机使用一个还有PP的技能
+    Random,
+}
+
+impl std::str::FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Policy::First),
+            "strongest" => Ok(Policy::Strongest),
+            "random" => Ok(Policy::Random),
+            _ => Err(anyhow::anyhow!(
+                "未知的策略 \"{}\", 可选 first/strongest/random",
+                s
+            )),
+        }
+    }
+}
+
+impl Policy {
+    /// 为上场的精灵选择本回合的行为, 无法行动时返回空
+    fn choose(
+        self,
+        game_state: &GameState,
+        player_id: u64,
+        rng: &mut StdRng,
+    ) -> Option<RoomAction> {
+        if !game_state.can_act(player_id) {
+            return None;
+        }
+        let sprite = game_state.current_sprite(player_id)?;
+        let usable = sprite.skills.iter().filter(|skill| skill.pp > 0);
+        let skill_id = match self {
+            Policy::First => return game_state.default_action(player_id),
+            Policy::Strongest => usable.max_by_key(|skill| skill.power)?.id,
+            Policy::Random => {
+                let usable: Vec<&Skill> = usable.collect();
+                usable[rng.random_range(0..usable.len())].id
+            }
+        };
+        Some(RoomAction::SkillAttack { skill_id })
+    }
+}
+
+/// 伤害统计
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DamageStats {
+    /// 造成伤害的次数
+    pub hits: u32,
+    pub total: u64,
+    pub min: u16,
+    pub max: u16,
+    /// 暴击次数
+    pub critical: u32,
+}
+
+impl DamageStats {
+    fn record(&mut self, damage: u16, is_critical: bool) {
+        self.min = if self.hits == 0 {
+            damage
+        } else {
+            self.min.min(damage)
+        };
+        self.max = self.max.max(damage);
+        self.hits += 1;
+        self.total += damage as u64;
+        if is_critical {
+            self.critical += 1;
+        }
+    }
+
+    pub fn average(&self) -> f64 {
+        if self.hits == 0 {
+            0.0
+        } else {
+            self.total as f64 / self.hits as f64
+        }
+    }
+}
+
+/// 技能的使用统计
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SkillStats {
+    pub name: String,
+    pub uses: u32,
+    pub misses: u32,
+    pub damage: DamageStats,
+}
+
+/// 多局模拟对战的统计结果
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimulationReport {
+    pub games: u32,
+    /// 玩家获胜的局数
+    pub wins: BTreeMap<u64, u32>,
+    pub draws: u32,
+    pub total_turns: u64,
+    /// 按(玩家ID, 技能ID)统计
+    pub skills: BTreeMap<(u64, u64), SkillStats>,
+    /// 按(技能属性, 防守方属性)统计
+    pub matchups: HashMap<(Attribute, Vec<Attribute>), DamageStats>,
+}
+
+impl SimulationReport {
+    pub fn win_rate(&self, player_id: u64) -> f64 {
+        if self.games == 0 {
+            return 0.0;
+        }
+        self.wins.get(&player_id).copied().unwrap_or(0) as f64 / self.games as f64
+    }
+
+    pub fn average_turns(&self) -> f64 {
+        if self.games == 0 {
+            return 0.0;
+        }
+        self.total_turns as f64 / self.games as f64
+    }
+
+    /// 统计一回合的战斗事件
+    ///
+    /// 需要在回合结算后、替换倒下的精灵前调用, 此时上场的精灵就是本回合出手的精灵
+    fn record_turn(&mut self, game_state: &GameState, events: &[BattleEvent]) {
+        let mut current_skill: Option<(u64, Skill)> = None;
+        for event in events {
+            match event {
+                BattleEvent::SkillUsed {
+                    player_id,
+                    skill_id,
+                } => {
+                    let skill = if *skill_id == STRUGGLE_SKILL_ID {
+                        Some(Skill::struggle())
+                    } else {
+                        game_state.current_sprite(*player_id).and_then(|sprite| {
+                            sprite
+                                .skills
+                                .iter()
+                                .find(|skill| skill.id == *skill_id)
+                                .cloned()
+                        })
+                    };
+                    let Some(skill) = skill else {
+                        current_skill = None;
+                        continue;
+                    };
+                    let stats = self.skills.entry((*player_id, *skill_id)).or_default();
+                    stats.name.clone_from(&skill.name);
+                    stats.uses += 1;
+                    current_skill = Some((*player_id, skill));
+                }
+                BattleEvent::Miss {
+                    player_id,
+                    skill_id,
+                } => {
+                    if let Some(stats) = self.skills.get_mut(&(*player_id, *skill_id)) {
+                        stats.misses += 1;
+                    }
+                }
+                // 挣扎对自己造成的反伤不计入技能伤害
+                BattleEvent::Damage {
+                    player_id,
+                    sprite_index,
+                    damage,
+                    is_critical,
+                    ..
+                } => {
+                    let Some((attacker_id, skill)) = &current_skill else {
+                        continue;
+                    };
+                    if attacker_id == player_id {
+                        continue;
+                    }
+                    if let Some(stats) = self.skills.get_mut(&(*attacker_id, skill.id)) {
+                        stats.damage.record(*damage, *is_critical);
+                    }
+                    let defender = game_state
+                        .sprite_teams
+                        .get(player_id)
+                        .and_then(|team| team.get(*sprite_index));
+                    if let Some(defender) = defender {
+                        self.matchups
+                            .entry((skill.attribute, defender_attributes(defender)))
+                            .or_default()
+                            .record(*damage, *is_critical);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "模拟对局: {}", self.games)?;
+        for player_id in PLAYERS {
+            writeln!(
+                f,
+                "玩家 {} 胜率: {:.2}%",
+                player_id,
+                self.win_rate(player_id) * 100.0
+            )?;
+        }
+        writeln!(f, "平局: {}", self.draws)?;
+        writeln!(f, "平均回合数: {:.2}", self.average_turns())?;
+        writeln!(f, "技能伤害分布:")?;
+        for ((player_id, skill_id), stats) in &self.skills {
+            writeln!(
+                f,
+                "  玩家 {} 技能 {}({}): 使用 {} 次, 未命中 {} 次, 命中 {} 次, 平均伤害 {:.1}, 最小 {}, 最大 {}, 暴击 {} 次",
+                player_id,
+                skill_id,
+                stats.name,
+                stats.uses,
+                stats.misses,
+                stats.damage.hits,
+                stats.damage.average(),
+                stats.damage.min,
+                stats.damage.max,
+                stats.damage.critical
+            )?;
+        }
+        writeln!(f, "属性克制伤害分布:")?;
+        let mut matchups: Vec<_> = self.matchups.iter().collect();
+        matchups.sort_by_key(|((attribute, target), _)| {
+            (format!("{:?}", attribute), format!("{:?}", target))
+        });
+        for ((attribute, target), stats) in matchups {
+            writeln!(
+                f,
+                "  {:?} -> {:?}: 命中 {} 次, 平均伤害 {:.1}, 最小 {}, 最大 {}",
+                attribute,
+                target,
+                stats.hits,
+                stats.average(),
+                stats.min,
+                stats.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 不经过网络与房间Actor, 直接使用战斗引擎模拟多局对战
+///
+/// 第 `n` 局使用 `seed + n` 作为随机数种子, 相同的参数总是得到相同的统计结果
+pub fn simulate(
+    teams: [&[Sprite]; 2],
+    rules: BattleRules,
+    policies: [Policy; 2],
+    games: u32,
+    seed: u64,
+) -> SimulationReport {
+    let mut report = SimulationReport::default();
+    for game in 0..games {
+        let game_seed = seed.wrapping_add(game as u64);
+        let mut game_state = GameState::new(PLAYERS, rules, game_seed);
+        // 策略使用独立的随机数生成器, 不影响战斗中的随机判定
+        let mut policy_rng = StdRng::seed_from_u64(!game_seed);
+        for (player_id, team) in PLAYERS.into_iter().zip(teams) {
+            game_state.submit_team(player_id, team.to_vec());
+        }
+        if !game_state.start_battle() {
+            println!("第 {} 局没有符合规则的精灵, 跳过", game);
+            continue;
+        }
+        while !game_state.is_end() {
+            if matches!(game_state.pk_state, PKState::WaitingReplacement { .. }) {
+                game_state.auto_replace();
+                continue;
+            }
+            for (player_id, policy) in PLAYERS.into_iter().zip(policies) {
+                if let Some(action) = policy.choose(&game_state, player_id, &mut policy_rng) {
+                    game_state.submit_action(player_id, action);
+                }
+            }
+            let events = game_state.resolve_turn();
+            report.record_turn(&game_state, &events);
+        }
+        let Some(result) = game_state.result else {
+            continue;
+        };
+        report.games += 1;
+        report.total_turns += result.turns as u64;
+        match result.winner {
+            Some(winner) => *report.wins.entry(winner).or_default() += 1,
+            None => report.draws += 1,
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use common::sprites::attributes::SkillType;
+
+    use super::*;
+    use crate::fixtures;
+
+    fn team(speed: u16) -> Vec<Sprite> {
+        let skills = vec![
+            Skill {
+                id: 1,
+                name: "火球".to_string(),
+                description: String::new(),
+                skill_type: SkillType::Magical,
+                attribute: Attribute::Huo,
+                pp: 20,
+                max_pp: 20,
+                power: 80,
+                accuracy: 90,
+                priority: 0,
+                special_effect: None,
+            },
+            Skill {
+                id: 2,
+                name: "撞击".to_string(),
+                description: String::new(),
+                skill_type: SkillType::Physical,
+                attribute: Attribute::None,
+                pp: 20,
+                max_pp: 20,
+                power: 40,
+                accuracy: 100,
+                priority: 0,
+                special_effect: None,
+            },
+        ];
+        (1..=2)
+            .map(|id| Sprite {
+                attributes: vec![Attribute::Huo],
+                speed,
+                skills: skills.clone(),
+                ..fixtures::sprite(id)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_simulate() {
+        let team_a = team(200);
+        let team_b = team(100);
+        let report = simulate(
+            [&team_a, &team_b],
+            BattleRules::default(),
+            [Policy::Strongest, Policy::Random],
+            20,
+            1,
+        );
+        assert_eq!(report.games, 20);
+        assert_eq!(report.wins.values().sum::<u32>() + report.draws, 20);
+        assert!(report.average_turns() > 0.0);
+        assert!(report.skills[&(1, 1)].uses > 0);
+        // 玩家1总是使用威力最大的技能
+        assert!(!report.skills.contains_key(&(1, 2)));
+        assert!(
+            report
+                .matchups
+                .contains_key(&(Attribute::Huo, vec![Attribute::Huo]))
+        );
+
+        // 相同的参数得到相同的统计结果
+        let again = simulate(
+            [&team_a, &team_b],
+            BattleRules::default(),
+            [Policy::Strongest, Policy::Random],
+            20,
+            1,
+        );
+        assert_eq!(report, again);
+    }
+}
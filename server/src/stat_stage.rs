@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use common::sprites::{Sprite, skills::BattleStat};
+
+use crate::status_effect::SpriteKey;
+
+/// 能力等级的上限, 下限为其相反数
+pub const MAX_STAGE: i8 = 6;
+
+/// 对战中精灵的能力等级
+///
+/// 精灵下场后能力等级重置
+#[derive(Debug, Default)]
+pub struct StatStages {
+    stages: HashMap<SpriteKey, HashMap<BattleStat, i8>>,
+}
+
+impl StatStages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 精灵某项能力当前的等级
+    pub fn get(&self, key: SpriteKey, stat: BattleStat) -> i8 {
+        self.stages
+            .get(&key)
+            .and_then(|stages| stages.get(&stat))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// 提升(正数)或降低(负数)精灵的能力等级, 返回实际变化的等级数
+    pub fn change(&mut self, key: SpriteKey, stat: BattleStat, delta: i8) -> i8 {
+        let stage = self.stages.entry(key).or_default().entry(stat).or_insert(0);
+        let before = *stage;
+        *stage = stage.saturating_add(delta).clamp(-MAX_STAGE, MAX_STAGE);
+        *stage - before
+    }
+
+    /// 重置精灵的能力等级
+    pub fn reset(&mut self, key: SpriteKey) {
+        self.stages.remove(&key);
+    }
+
+    /// 将能力等级计入精灵面板
+    pub fn apply(&self, key: SpriteKey, sprite: &mut Sprite) {
+        for stat in BattleStat::ALL {
+            let multiplier = stage_multiplier(self.get(key, stat));
+            let value = match stat {
+                BattleStat::PhyAtk => &mut sprite.phy_atk,
+                BattleStat::PhyDef => &mut sprite.phy_def,
+                BattleStat::MagAtk => &mut sprite.mag_atk,
+                BattleStat::MagDef => &mut sprite.mag_def,
+                BattleStat::Speed => &mut sprite.speed,
+            };
+            *value = (*value as f32 * multiplier).min(u16::MAX as f32) as u16;
+        }
+    }
+}
+
+/// 能力等级对应的倍数, 每提升一级增加50%, 每降低一级按相同比例减少
+///
+/// `+n` 级为 `(2 + n) / 2`, `-n` 级为 `2 / (2 + n)`
+pub fn stage_multiplier(stage: i8) -> f32 {
+    let stage = stage.clamp(-MAX_STAGE, MAX_STAGE) as f32;
+    if stage >= 0.0 {
+        (2.0 + stage) / 2.0
+    } else {
+        2.0 / (2.0 - stage)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_change_is_clamped() {
+        let mut stat_stages = StatStages::new();
+        let key = (1, 0);
+        assert_eq!(stat_stages.change(key, BattleStat::PhyAtk, 4), 4);
+        assert_eq!(stat_stages.change(key, BattleStat::PhyAtk, 4), 2);
+        assert_eq!(stat_stages.get(key, BattleStat::PhyAtk), MAX_STAGE);
+        assert_eq!(stat_stages.change(key, BattleStat::Speed, -8), -6);
+        stat_stages.reset(key);
+        assert_eq!(stat_stages.get(key, BattleStat::PhyAtk), 0);
+    }
+
+    #[test]
+    fn test_stage_multiplier() {
+        assert_eq!(stage_multiplier(0), 1.0);
+        assert_eq!(stage_multiplier(2), 2.0);
+        assert_eq!(stage_multiplier(6), 4.0);
+        assert_eq!(stage_multiplier(-2), 0.5);
+        assert_eq!(stage_multiplier(-6), 0.25);
+    }
+}
@@ -1,10 +1,18 @@
-#[derive(Debug, Clone, Copy, Default)]
+use chrono::Utc;
+
+#[derive(Debug, Clone, Default)]
 pub struct PlayerSession {
     // ... other fields like player_id, stream
     player_id: u64,
     room_id: Option<u64>,
     last_client_sequence: u64,
     server_sequence: u64,
+    /// 最新下发的 token 的过期时间(Unix 时间戳, 秒)
+    token_expires_at: Option<usize>,
+    /// 最后一次收到客户端消息的时间(Unix 时间戳, 秒)
+    last_active_at: i64,
+    /// 携带其他玩家 token 的次数
+    auth_violations: u32,
 }
 
 impl PlayerSession {
@@ -14,6 +22,9 @@ impl PlayerSession {
             room_id: None,
             last_client_sequence,
             server_sequence,
+            token_expires_at: None,
+            last_active_at: Utc::now().timestamp(),
+            auth_violations: 0,
         }
     }
 
@@ -41,7 +52,46 @@ impl PlayerSession {
         self.room_id = Some(room_id);
     }
 
+    pub fn clear_room_id(&mut self) {
+        self.room_id = None;
+    }
+
     pub fn room_id(&self) -> Option<u64> {
         self.room_id
     }
+
+    pub fn token_expires_at(&self) -> Option<usize> {
+        self.token_expires_at
+    }
+
+    /// 记录 token 的过期时间, 只保留最晚的过期时间
+    pub fn update_token_expires_at(&mut self, expires_at: usize) {
+        if self
+            .token_expires_at
+            .is_none_or(|current| current < expires_at)
+        {
+            self.token_expires_at = Some(expires_at);
+        }
+    }
+
+    /// 停止主动刷新 token, 直到客户端再次携带 token 请求
+    pub fn clear_token_expires_at(&mut self) {
+        self.token_expires_at = None;
+    }
+
+    /// 记录客户端活跃
+    pub fn touch(&mut self) {
+        self.last_active_at = Utc::now().timestamp();
+    }
+
+    /// 客户端已空闲的秒数
+    pub fn idle_secs(&self) -> i64 {
+        Utc::now().timestamp() - self.last_active_at
+    }
+
+    /// 记录一次携带其他玩家 token 的行为, 返回累计次数
+    pub fn record_auth_violation(&mut self) -> u32 {
+        self.auth_violations += 1;
+        self.auth_violations
+    }
 }
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+
+use common::{buff_effect::ExceptionEffect, message::BattleEvent, sprites::Sprite};
+
+/// 精灵的标识: (玩家ID, 精灵在队伍中的索引)
+///
+/// 异常状态跟随精灵本身, 精灵下场后再上场时仍然保留
+pub type SpriteKey = (u64, usize);
+
+/// 控制效果结束后免疫控制效果的回合数, 避免被连续控制
+const CONTROL_IMMUNITY_TURNS: u8 = 1;
+
+/// 异常状态的作用方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectKind {
+    /// 无法行动
+    Control,
+    /// 每回合结束时按最大HP的百分比扣除HP
+    Damage { percent: u16 },
+    /// 攻击力(物攻、法攻)按百分比降低
+    AttackPenalty { percent: u16 },
+    /// 速度按百分比降低
+    SpeedPenalty { percent: u16 },
+}
+
+/// 异常状态的作用方式, [`ExceptionEffect::Normal`] 没有效果
+pub fn effect_kind(effect: ExceptionEffect) -> Option<EffectKind> {
+    match effect {
+        ExceptionEffect::Normal => None,
+        ExceptionEffect::Stun | ExceptionEffect::Freeze | ExceptionEffect::Numbness => {
+            Some(EffectKind::Control)
+        }
+        ExceptionEffect::Fatigue => Some(EffectKind::AttackPenalty { percent: 50 }),
+        ExceptionEffect::Sinking => Some(EffectKind::SpeedPenalty { percent: 50 }),
+        ExceptionEffect::Poisoning => Some(EffectKind::Damage { percent: 8 }),
+        ExceptionEffect::Bleeding | ExceptionEffect::Burn => {
+            Some(EffectKind::Damage { percent: 6 })
+        }
+        // 剧毒每回合的伤害递增, 这里是第一回合的百分比
+        ExceptionEffect::HighlyToxic => Some(EffectKind::Damage { percent: 6 }),
+    }
+}
+
+/// 精灵身上生效中的异常状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveEffect {
+    pub effect: ExceptionEffect,
+    /// 剩余回合数
+    pub remaining_turns: u8,
+    /// 已经结算的回合数, 剧毒的伤害随之递增
+    pub elapsed_turns: u8,
+    /// 本回合刚施加的效果, 回合结束时不结算
+    fresh: bool,
+}
+
+/// 对战中所有精灵的异常状态
+///
+/// 叠加规则:
+/// * 同一种异常状态重复施加时刷新为较长的持续回合
+/// * 同一时间只能有一种控制效果(击昏、冰冻、麻痹), 控制效果结束后短暂免疫控制
+/// * 剧毒会覆盖中毒, 处于剧毒时无法再中毒
+/// * 灼伤与冰冻互相抵消, 施加其中一种会解除另一种
+/// * 倒下的精灵不会陷入异常状态
+#[derive(Debug, Default)]
+pub struct StatusEffects {
+    effects: HashMap<SpriteKey, Vec<ActiveEffect>>,
+    /// 控制效果免疫的剩余回合数
+    control_immunity: HashMap<SpriteKey, u8>,
+}
+
+impl StatusEffects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 精灵身上生效中的异常状态
+    pub fn effects(&self, key: SpriteKey) -> &[ActiveEffect] {
+        self.effects.get(&key).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn has(&self, key: SpriteKey, effect: ExceptionEffect) -> bool {
+        self.effects(key)
+            .iter()
+            .any(|active| active.effect == effect)
+    }
+
+    /// 使精灵无法行动的控制效果
+    pub fn control_effect(&self, key: SpriteKey) -> Option<ExceptionEffect> {
+        self.effects(key)
+            .iter()
+            .map(|active| active.effect)
+            .find(|effect| effect_kind(*effect) == Some(EffectKind::Control))
+    }
+
+    /// 精灵是否没有受到控制效果, 可以行动
+    pub fn can_act(&self, key: SpriteKey) -> bool {
+        self.control_effect(key).is_none()
+    }
+
+    /// 异常状态对攻击力的倍数
+    pub fn attack_multiplier(&self, key: SpriteKey) -> f32 {
+        self.penalty_multiplier(key, |kind| match kind {
+            EffectKind::AttackPenalty { percent } => Some(percent),
+            _ => None,
+        })
+    }
+
+    /// 异常状态对速度的倍数
+    pub fn speed_multiplier(&self, key: SpriteKey) -> f32 {
+        self.penalty_multiplier(key, |kind| match kind {
+            EffectKind::SpeedPenalty { percent } => Some(percent),
+            _ => None,
+        })
+    }
+
+    fn penalty_multiplier(
+        &self,
+        key: SpriteKey,
+        penalty: impl Fn(EffectKind) -> Option<u16>,
+    ) -> f32 {
+        self.effects(key)
+            .iter()
+            .filter_map(|active| effect_kind(active.effect).and_then(&penalty))
+            .fold(1.0, |multiplier, percent| {
+                multiplier * (100 - percent.min(100)) as f32 / 100.0
+            })
+    }
+
+    /// 对精灵施加异常状态, 返回是否生效
+    ///
+    /// 生效或解除的异常状态会记录到 `events` 中
+    pub fn apply(
+        &mut self,
+        key: SpriteKey,
+        sprite: &Sprite,
+        effect: ExceptionEffect,
+        duration: u8,
+        events: &mut Vec<BattleEvent>,
+    ) -> bool {
+        let Some(kind) = effect_kind(effect) else {
+            return false;
+        };
+        if duration == 0 || sprite.hp == 0 {
+            return false;
+        }
+        if kind == EffectKind::Control
+            && (self.control_immunity.contains_key(&key)
+                || self
+                    .control_effect(key)
+                    .is_some_and(|active| active != effect))
+        {
+            return false;
+        }
+        if effect == ExceptionEffect::Poisoning && self.has(key, ExceptionEffect::HighlyToxic) {
+            return false;
+        }
+
+        let replaced = match effect {
+            ExceptionEffect::HighlyToxic => Some(ExceptionEffect::Poisoning),
+            ExceptionEffect::Burn => Some(ExceptionEffect::Freeze),
+            ExceptionEffect::Freeze => Some(ExceptionEffect::Burn),
+            _ => None,
+        };
+        if let Some(replaced) = replaced {
+            self.remove(key, replaced, events);
+        }
+
+        let effects = self.effects.entry(key).or_default();
+        match effects.iter_mut().find(|active| active.effect == effect) {
+            Some(active) => {
+                active.remaining_turns = active.remaining_turns.max(duration);
+            }
+            None => effects.push(ActiveEffect {
+                effect,
+                remaining_turns: duration,
+                elapsed_turns: 0,
+                fresh: true,
+            }),
+        }
+        events.push(BattleEvent::StatusApplied {
+            player_id: key.0,
+            sprite_index: key.1,
+            effect,
+        });
+        true
+    }
+
+    /// 解除精灵身上的某种异常状态
+    pub fn remove(
+        &mut self,
+        key: SpriteKey,
+        effect: ExceptionEffect,
+        events: &mut Vec<BattleEvent>,
+    ) {
+        let Some(effects) = self.effects.get_mut(&key) else {
+            return;
+        };
+        let len = effects.len();
+        effects.retain(|active| active.effect != effect);
+        if effects.len() != len {
+            events.push(BattleEvent::StatusCleared {
+                player_id: key.0,
+                sprite_index: key.1,
+                effect,
+            });
+        }
+    }
+
+    /// 精灵倒下时清除所有异常状态
+    pub fn clear(&mut self, key: SpriteKey) {
+        self.effects.remove(&key);
+        self.control_immunity.remove(&key);
+    }
+
+    /// 回合结束时结算精灵身上的异常状态
+    ///
+    /// 1. 持续伤害类的异常状态按最大HP的百分比扣除HP, 至少扣除1点
+    /// 2. 持续回合数减一, 结束的异常状态被解除
+    /// 3. 本回合刚施加的异常状态不结算, 从下一回合开始计算
+    ///
+    /// 返回精灵是否因持续伤害倒下, 倒下的精灵由调用方统一处理
+    pub fn tick(
+        &mut self,
+        key: SpriteKey,
+        sprite: &mut Sprite,
+        events: &mut Vec<BattleEvent>,
+    ) -> bool {
+        if let Some(turns) = self.control_immunity.get_mut(&key) {
+            *turns = turns.saturating_sub(1);
+            if *turns == 0 {
+                self.control_immunity.remove(&key);
+            }
+        }
+        let Some(effects) = self.effects.get_mut(&key) else {
+            return false;
+        };
+        let (player_id, sprite_index) = key;
+        let mut control_ended = false;
+        for active in effects.iter_mut() {
+            if active.fresh {
+                active.fresh = false;
+                continue;
+            }
+            if sprite.hp == 0 {
+                break;
+            }
+            active.elapsed_turns += 1;
+            if let Some(EffectKind::Damage { percent }) = effect_kind(active.effect) {
+                let percent = if active.effect == ExceptionEffect::HighlyToxic {
+                    percent * active.elapsed_turns as u16
+                } else {
+                    percent
+                };
+                let damage = (sprite.max_hp as u32 * percent as u32 / 100).max(1) as u16;
+                sprite.hp = sprite.hp.saturating_sub(damage);
+                events.push(BattleEvent::StatusDamage {
+                    player_id,
+                    sprite_index,
+                    effect: active.effect,
+                    damage,
+                    remaining_hp: sprite.hp,
+                });
+            }
+            active.remaining_turns = active.remaining_turns.saturating_sub(1);
+        }
+
+        if sprite.hp == 0 {
+            return true;
+        }
+        effects.retain(|active| {
+            if active.remaining_turns > 0 {
+                return true;
+            }
+            control_ended |= effect_kind(active.effect) == Some(EffectKind::Control);
+            events.push(BattleEvent::StatusCleared {
+                player_id,
+                sprite_index,
+                effect: active.effect,
+            });
+            false
+        });
+        if control_ended {
+            self.control_immunity.insert(key, CONTROL_IMMUNITY_TURNS);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn test_damage_over_time() {
+        let mut status_effects = StatusEffects::new();
+        let mut sprite = fixtures::sprite(1);
+        let mut events = vec![];
+        let key = (1, 0);
+        assert!(status_effects.apply(key, &sprite, ExceptionEffect::HighlyToxic, 3, &mut events));
+        // 施加的回合不结算
+        status_effects.tick(key, &mut sprite, &mut events);
+        assert_eq!(sprite.hp, 400);
+        // 剧毒伤害逐回合递增: 6%, 12%, 18%
+        status_effects.tick(key, &mut sprite, &mut events);
+        assert_eq!(sprite.hp, 376);
+        status_effects.tick(key, &mut sprite, &mut events);
+        assert_eq!(sprite.hp, 328);
+        status_effects.tick(key, &mut sprite, &mut events);
+        assert_eq!(sprite.hp, 256);
+        assert!(status_effects.effects(key).is_empty());
+        assert!(matches!(
+            events.last(),
+            Some(BattleEvent::StatusCleared {
+                effect: ExceptionEffect::HighlyToxic,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_stacking_rules() {
+        let mut status_effects = StatusEffects::new();
+        let sprite = fixtures::sprite(1);
+        let mut events = vec![];
+        let key = (1, 0);
+
+        assert!(status_effects.apply(key, &sprite, ExceptionEffect::Poisoning, 2, &mut events));
+        assert!(status_effects.apply(key, &sprite, ExceptionEffect::HighlyToxic, 2, &mut events));
+        assert!(!status_effects.has(key, ExceptionEffect::Poisoning));
+        assert!(!status_effects.apply(key, &sprite, ExceptionEffect::Poisoning, 2, &mut events));
+
+        assert!(status_effects.apply(key, &sprite, ExceptionEffect::Burn, 2, &mut events));
+        assert!(status_effects.apply(key, &sprite, ExceptionEffect::Freeze, 2, &mut events));
+        assert!(!status_effects.has(key, ExceptionEffect::Burn));
+        // 已经被冰冻时无法再被击昏, 重复冰冻刷新持续回合
+        assert!(!status_effects.apply(key, &sprite, ExceptionEffect::Stun, 2, &mut events));
+        assert!(status_effects.apply(key, &sprite, ExceptionEffect::Freeze, 3, &mut events));
+        assert_eq!(
+            status_effects.control_effect(key),
+            Some(ExceptionEffect::Freeze)
+        );
+        assert_eq!(
+            status_effects
+                .effects(key)
+                .iter()
+                .find(|active| active.effect == ExceptionEffect::Freeze)
+                .unwrap()
+                .remaining_turns,
+            3
+        );
+    }
+
+    #[test]
+    fn test_control_immunity_after_control_ends() {
+        let mut status_effects = StatusEffects::new();
+        let mut sprite = fixtures::sprite(1);
+        let mut events = vec![];
+        let key = (1, 0);
+
+        assert!(status_effects.apply(key, &sprite, ExceptionEffect::Stun, 1, &mut events));
+        status_effects.tick(key, &mut sprite, &mut events);
+        assert_eq!(
+            status_effects.control_effect(key),
+            Some(ExceptionEffect::Stun)
+        );
+        status_effects.tick(key, &mut sprite, &mut events);
+        assert_eq!(status_effects.control_effect(key), None);
+
+        // 控制结束后的下一回合免疫控制
+        assert!(!status_effects.apply(key, &sprite, ExceptionEffect::Numbness, 1, &mut events));
+        status_effects.tick(key, &mut sprite, &mut events);
+        assert!(status_effects.apply(key, &sprite, ExceptionEffect::Numbness, 1, &mut events));
+    }
+
+    #[test]
+    fn test_penalties() {
+        let mut status_effects = StatusEffects::new();
+        let sprite = fixtures::sprite(1);
+        let mut events = vec![];
+        let key = (1, 0);
+        assert_eq!(status_effects.attack_multiplier(key), 1.0);
+        status_effects.apply(key, &sprite, ExceptionEffect::Fatigue, 2, &mut events);
+        status_effects.apply(key, &sprite, ExceptionEffect::Sinking, 2, &mut events);
+        assert_eq!(status_effects.attack_multiplier(key), 0.5);
+        assert_eq!(status_effects.speed_multiplier(key), 0.5);
+    }
+}